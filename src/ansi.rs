@@ -0,0 +1,267 @@
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+static ESCAPE_RE: OnceLock<Regex> = OnceLock::new();
+
+// CSI sequences, OSC sequences (BEL or ST terminated), other two byte escapes and
+// the remaining C0 control characters except tab and newline
+fn get_escape_regex() -> &'static Regex {
+    ESCAPE_RE.get_or_init(|| {
+        Regex::new(concat!(
+            r"\x1b\[[0-?]*[ -/]*[@-~]",
+            r"|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)?",
+            r"|\x1b[@-_]?",
+            r"|[\x00-\x08\x0b-\x1f\x7f]",
+        ))
+        .unwrap()
+    })
+}
+
+fn is_sgr(sequence: &str) -> bool {
+    sequence.starts_with("\x1b[") && sequence.ends_with('m')
+}
+
+/// Removes all escape sequences and control characters (except `\t` and `\n`).
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    get_escape_regex().replace_all(text, "")
+}
+
+/// Keeps SGR (colour) sequences but removes everything which could move the cursor,
+/// clear the screen or otherwise break the status line: other CSI sequences, OSC,
+/// `\r` and the remaining control characters.
+pub fn sanitize_ansi(text: &str) -> Cow<'_, str> {
+    get_escape_regex().replace_all(text, |caps: &Captures| {
+        let sequence = &caps[0];
+        if is_sgr(sequence) {
+            sequence.to_string()
+        } else {
+            String::new()
+        }
+    })
+}
+
+pub fn has_sgr(text: &str) -> bool {
+    text.contains("\x1b[")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const COLORS: [&str; 8] = [
+    "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+];
+const BRIGHT_COLORS: [&str; 8] = [
+    "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+];
+
+// xterm's 256 colour palette: 16 base colours, a 6x6x6 cube and 24 greys
+fn color_256(n: u8) -> String {
+    match n {
+        0..=7 => COLORS[n as usize].to_string(),
+        8..=15 => BRIGHT_COLORS[n as usize - 8].to_string(),
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(n / 36),
+                level(n / 6 % 6),
+                level(n % 6)
+            )
+        }
+        _ => {
+            let grey = 8 + (n - 232) * 10;
+            format!("#{:02x}{:02x}{:02x}", grey, grey, grey)
+        }
+    }
+}
+
+#[derive(Default, Clone, PartialEq)]
+struct Style {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    fg: Option<String>,
+    bg: Option<String>,
+}
+
+impl Style {
+    fn apply(&mut self, params: &str) {
+        let codes: Vec<u8> = params
+            .split(';')
+            .map(|code| code.parse().unwrap_or(0))
+            .collect();
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                code @ 30..=37 => self.fg = Some(COLORS[code as usize - 30].to_string()),
+                code @ 90..=97 => self.fg = Some(BRIGHT_COLORS[code as usize - 90].to_string()),
+                code @ 40..=47 => self.bg = Some(COLORS[code as usize - 40].to_string()),
+                code @ 100..=107 => self.bg = Some(BRIGHT_COLORS[code as usize - 100].to_string()),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                code @ (38 | 48) => {
+                    let color = match codes.get(i + 1) {
+                        Some(5) => {
+                            i += 2;
+                            codes.get(i).map(|&n| color_256(n))
+                        }
+                        Some(2) if i + 4 < codes.len() => {
+                            i += 4;
+                            Some(format!(
+                                "#{:02x}{:02x}{:02x}",
+                                codes[i - 2],
+                                codes[i - 1],
+                                codes[i]
+                            ))
+                        }
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if self.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if self.dim {
+            css.push("opacity:0.6".to_string());
+        }
+        if self.italic {
+            css.push("font-style:italic".to_string());
+        }
+        if self.underline {
+            css.push("text-decoration:underline".to_string());
+        }
+        if let Some(fg) = &self.fg {
+            css.push(format!("color:{}", fg));
+        }
+        if let Some(bg) = &self.bg {
+            css.push(format!("background:{}", bg));
+        }
+        css.join(";")
+    }
+}
+
+/// Renders the SGR (colour) sequences of `text` as HTML `<span>`s, everything else
+/// is escaped and the other escape sequences are dropped.
+pub fn ansi_to_html(text: &str) -> String {
+    let mut html = String::new();
+    let mut style = Style::default();
+    let mut last = 0;
+    for found in get_escape_regex().find_iter(text) {
+        let chunk = &text[last..found.start()];
+        push_styled(&mut html, &style, chunk);
+        last = found.end();
+        let sequence = found.as_str();
+        if is_sgr(sequence) {
+            style.apply(&sequence[2..sequence.len() - 1]);
+        }
+    }
+    push_styled(&mut html, &style, &text[last..]);
+    html
+}
+
+fn push_styled(html: &mut String, style: &Style, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let css = style.css();
+    if css.is_empty() {
+        html.push_str(&escape_html(text));
+    } else {
+        html.push_str(&format!(
+            "<span style=\"{}\">{}</span>",
+            css,
+            escape_html(text)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = "\x1b[1m\x1b[31merror\x1b[0m: \x1b]8;;https://x\x07link\x1b]8;;\x07\x1b[2K\r 10%\x08\tdone\n";
+
+    #[test]
+    fn strip() {
+        assert_eq!(strip_ansi(LINE), "error: link 10%\tdone\n");
+        assert!(matches!(strip_ansi("plain text"), Cow::Borrowed(_)));
+        // a lone ESC at the end of a truncated line
+        assert_eq!(strip_ansi("cut\x1b"), "cut");
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(
+            sanitize_ansi(LINE),
+            "\x1b[1m\x1b[31merror\x1b[0m: link 10%\tdone\n"
+        );
+        // cursor movement is a CSI sequence too, but no colour
+        assert_eq!(sanitize_ansi("\x1b[1A\x1b[38;5;9mred"), "\x1b[38;5;9mred");
+    }
+
+    #[test]
+    fn sgr() {
+        assert!(has_sgr("\x1b[32mok\x1b[0m"));
+        assert!(!has_sgr("ok"));
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(ansi_to_html("<b> & \"c\""), "&lt;b&gt; &amp; &quot;c&quot;");
+        assert_eq!(
+            ansi_to_html("\x1b[1;31merror\x1b[0m: <T>"),
+            "<span style=\"font-weight:bold;color:#cd3131\">error</span>: &lt;T&gt;"
+        );
+        // other escape sequences are dropped, the style carries over them
+        assert_eq!(
+            ansi_to_html("\x1b[4m\x1b[2Kunder\x1b[24m\x1b]8;;https://x\x07 plain"),
+            "<span style=\"text-decoration:underline\">under</span> plain"
+        );
+    }
+
+    #[test]
+    fn html_colors() {
+        assert_eq!(
+            ansi_to_html("\x1b[38;5;196mred\x1b[39m"),
+            "<span style=\"color:#ff0000\">red</span>"
+        );
+        assert_eq!(
+            ansi_to_html("\x1b[48;2;1;2;3mrgb\x1b[49m"),
+            "<span style=\"background:#010203\">rgb</span>"
+        );
+        assert_eq!(
+            ansi_to_html("\x1b[38;5;244mgrey\x1b[92mbright"),
+            "<span style=\"color:#808080\">grey</span><span style=\"color:#23d18b\">bright</span>"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use serde::Serialize;
+
+/// Instructions a build script printed on stdout, see
+/// https://doc.rust-lang.org/cargo/reference/build-scripts.html#outputs-of-the-build-script
+///
+/// Both the `cargo:KEY=VALUE` and the newer `cargo::KEY=VALUE` syntax are understood.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildScriptOutput {
+    pub rerun_if_changed: Vec<String>,
+    pub rerun_if_env_changed: Vec<String>,
+    pub rustc_cfg: Vec<String>,
+    pub rustc_check_cfg: Vec<String>,
+    pub rustc_env: Vec<(String, String)>,
+    pub rustc_link_lib: Vec<String>,
+    pub rustc_link_search: Vec<String>,
+    pub rustc_link_arg: Vec<String>,
+    pub rustc_flags: Vec<String>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+    /// `cargo:KEY=VALUE` for any other key, passed to dependents as `DEP_<links>_<KEY>`
+    pub metadata: Vec<(String, String)>,
+    /// everything which is not an instruction
+    pub other: Vec<String>,
+}
+
+impl BuildScriptOutput {
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut output = BuildScriptOutput::default();
+        for line in lines {
+            output.parse_line(line);
+        }
+        output
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        let instruction = line
+            .strip_prefix("cargo::")
+            .or_else(|| line.strip_prefix("cargo:"))
+            .and_then(|instruction| instruction.split_once('='));
+        let (key, value) = match instruction {
+            Some((key, value)) => (key, value.to_string()),
+            None => {
+                self.other.push(line.to_string());
+                return;
+            }
+        };
+        match key {
+            "rerun-if-changed" => self.rerun_if_changed.push(value),
+            "rerun-if-env-changed" => self.rerun_if_env_changed.push(value),
+            "rustc-cfg" => self.rustc_cfg.push(value),
+            "rustc-check-cfg" => self.rustc_check_cfg.push(value),
+            "rustc-env" => {
+                let (name, value) = value.split_once('=').unwrap_or((&value, ""));
+                self.rustc_env.push((name.to_string(), value.to_string()));
+            }
+            "rustc-link-lib" => self.rustc_link_lib.push(value),
+            "rustc-link-search" => self.rustc_link_search.push(value),
+            "rustc-link-arg"
+            | "rustc-link-arg-bins"
+            | "rustc-link-arg-bin"
+            | "rustc-link-arg-tests"
+            | "rustc-link-arg-examples"
+            | "rustc-link-arg-benches"
+            | "rustc-cdylib-link-arg" => self.rustc_link_arg.push(value),
+            "rustc-flags" => self.rustc_flags.push(value),
+            "warning" => self.warnings.push(value),
+            "error" => self.errors.push(value),
+            "metadata" => {
+                let (name, value) = value.split_once('=').unwrap_or((&value, ""));
+                self.metadata.push((name.to_string(), value.to_string()));
+            }
+            _ => self.metadata.push((key.to_string(), value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes() {
+        let output = BuildScriptOutput::parse([
+            "cargo:rustc-cfg=freebsd11",
+            "cargo::rustc-cfg=libc_const_extern_fn",
+            "cargo::rustc-check-cfg=cfg(freebsd11)",
+            "cargo:rerun-if-changed=build.rs",
+            "cargo:warning=old syntax",
+            "cargo::warning=new syntax",
+            "running build script",
+            "cargo:no instruction",
+        ]);
+        assert_eq!(output.rustc_cfg, ["freebsd11", "libc_const_extern_fn"]);
+        assert_eq!(output.rustc_check_cfg, ["cfg(freebsd11)"]);
+        assert_eq!(output.rerun_if_changed, ["build.rs"]);
+        assert_eq!(output.warnings, ["old syntax", "new syntax"]);
+        assert_eq!(
+            output.other,
+            ["running build script", "cargo:no instruction"]
+        );
+    }
+
+    #[test]
+    fn key_value_pairs() {
+        let output = BuildScriptOutput::parse([
+            "cargo:rustc-env=VERSION=1.2=3",
+            "cargo:rustc-env=EMPTY",
+            "cargo:root=/nix/store/0aa-zlib/out",
+            "cargo::metadata=include=/nix/store/0aa-zlib/include",
+            "cargo:rustc-link-arg-bins=-Wl,-z,relro",
+            "cargo:rustc-link-lib=static=z",
+        ]);
+        assert_eq!(
+            output.rustc_env,
+            [
+                ("VERSION".to_string(), "1.2=3".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(
+            output.metadata,
+            [
+                ("root".to_string(), "/nix/store/0aa-zlib/out".to_string()),
+                (
+                    "include".to_string(),
+                    "/nix/store/0aa-zlib/include".to_string()
+                ),
+            ]
+        );
+        assert_eq!(output.rustc_link_arg, ["-Wl,-z,relro"]);
+        assert_eq!(output.rustc_link_lib, ["static=z"]);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::rustc::{workspace_path, RustcDiagnostic};
+use chrono::Utc;
+use clap::ValueEnum;
+
+/// Markup for CI systems, set with `LogOne::with_ci`.
+#[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
+pub enum CiFormat {
+    /// `::error`/`::warning` annotations and `::group::` around build logs
+    Github,
+    /// collapsible sections around build logs
+    Gitlab,
+}
+
+impl CiFormat {
+    /// The CI logone runs in, from the variables the runners set.
+    pub fn detect() -> Option<CiFormat> {
+        if std::env::var_os("GITHUB_ACTIONS").is_some() {
+            Some(CiFormat::Github)
+        } else if std::env::var_os("GITLAB_CI").is_some() {
+            Some(CiFormat::Gitlab)
+        } else {
+            None
+        }
+    }
+}
+
+// https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// `::error file=src/lib.rs,line=3,col=5,...::message` for a rustc diagnostic.
+pub fn github_annotation(diagnostic: &RustcDiagnostic) -> Option<String> {
+    let command = match diagnostic.level.as_str() {
+        level if level.starts_with("error") => "error",
+        "warning" => "warning",
+        "note" | "help" => "notice",
+        _ => return None,
+    };
+    let mut properties: Vec<String> = Vec::new();
+    if let Some(span) = diagnostic.primary_span() {
+        properties.push(format!(
+            "file={}",
+            escape_property(&workspace_path(&span.file_name))
+        ));
+        properties.push(format!("line={}", span.line_start));
+        properties.push(format!("endLine={}", span.line_end));
+        if span.line_start == span.line_end {
+            properties.push(format!("col={}", span.column_start));
+            properties.push(format!("endColumn={}", span.column_end));
+        }
+    }
+    if let Some(code) = diagnostic.code() {
+        properties.push(format!("title={}", escape_property(code)));
+    }
+    Some(format!(
+        "::{} {}::{}",
+        command,
+        properties.join(","),
+        escape_data(&diagnostic.message)
+    ))
+}
+
+/// `::error title=...::message` without a location, e.g. for failed derivations.
+pub fn github_message(command: &str, title: &str, message: &str) -> String {
+    format!(
+        "::{} title={}::{}",
+        command,
+        escape_property(title),
+        escape_data(message)
+    )
+}
+
+/// The line opening a collapsible section, `name` is only used by GitLab.
+pub fn group_start(ci: CiFormat, name: &str, title: &str) -> String {
+    match ci {
+        CiFormat::Github => format!("::group::{}", title),
+        CiFormat::Gitlab => format!(
+            "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+            Utc::now().timestamp(),
+            section_name(name),
+            title
+        ),
+    }
+}
+
+pub fn group_end(ci: CiFormat, name: &str) -> String {
+    match ci {
+        CiFormat::Github => "::endgroup::".to_string(),
+        CiFormat::Gitlab => format!(
+            "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+            Utc::now().timestamp(),
+            section_name(name)
+        ),
+    }
+}
+
+// GitLab only allows letters, digits, '_', '.' and '-' in section names
+fn section_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::rustc_diagnostic;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_data("100% done\r\nnext"), "100%25 done%0D%0Anext");
+        assert_eq!(escape_data("a: b, c"), "a: b, c");
+        assert_eq!(escape_property("a: b, c"), "a%3A b%2C c");
+        assert_eq!(escape_property("50%\n"), "50%25%0A");
+    }
+
+    #[test]
+    fn github_annotations() {
+        let diagnostic = RustcDiagnostic::from_value(&rustc_diagnostic(
+            "warning",
+            Some("unused_variables"),
+            "unused variable: `x`",
+            "/build/regex-1.11.1/src/lib.rs",
+            3,
+            9,
+        ))
+        .unwrap();
+        assert_eq!(
+            github_annotation(&diagnostic).unwrap(),
+            "::warning file=src/lib.rs,line=3,endLine=3,col=9,endColumn=10,\
+             title=unused_variables::unused variable: `x`"
+        );
+
+        let diagnostic = RustcDiagnostic::from_value(&rustc_diagnostic(
+            "error: internal compiler error",
+            None,
+            "unexpected panic\nat 100%",
+            "src/main.rs",
+            1,
+            1,
+        ))
+        .unwrap();
+        assert_eq!(
+            github_annotation(&diagnostic).unwrap(),
+            "::error file=src/main.rs,line=1,endLine=1,col=1,endColumn=2::unexpected panic%0Aat 100%25"
+        );
+
+        let diagnostic = RustcDiagnostic::from_value(&rustc_diagnostic(
+            "failure-note",
+            None,
+            "Some errors have detailed explanations",
+            "src/main.rs",
+            1,
+            1,
+        ))
+        .unwrap();
+        assert_eq!(github_annotation(&diagnostic), None);
+
+        assert_eq!(
+            github_message("error", "hello: 2.12", "builder failed\n"),
+            "::error title=hello%3A 2.12::builder failed%0A"
+        );
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(
+            group_start(CiFormat::Github, "hello-2.12.1", "hello 2.12.1"),
+            "::group::hello 2.12.1"
+        );
+        assert_eq!(group_end(CiFormat::Github, "hello-2.12.1"), "::endgroup::");
+
+        let start = group_start(CiFormat::Gitlab, "hello 2.12+1", "hello 2.12.1");
+        assert!(start.starts_with("\x1b[0Ksection_start:"));
+        assert!(start.ends_with(":hello_2.12_1[collapsed=true]\r\x1b[0Khello 2.12.1"));
+        let end = group_end(CiFormat::Gitlab, "hello 2.12+1");
+        assert!(end.starts_with("\x1b[0Ksection_end:"));
+        assert!(end.ends_with(":hello_2.12_1\r\x1b[0K"));
+    }
+}
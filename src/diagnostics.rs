@@ -0,0 +1,104 @@
+use crate::error::LogoneError;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Collects problems found in the input stream: malformed lines, messages logone
+/// does not know and protocol violations like stops for ids which were never started.
+///
+/// Every problem is counted; it is only written out if an output was configured.
+#[derive(Default)]
+pub struct Diagnostics {
+    output: Option<Box<dyn Write>>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl Diagnostics {
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = Some(output);
+    }
+
+    pub fn record(&mut self, error: &LogoneError) {
+        *self.counts.entry(error.kind().category()).or_insert(0) += 1;
+        if let Some(output) = self.output.as_mut() {
+            let _ = writeln!(output, "{}", format_diagnostic(error));
+        }
+    }
+
+    /// Total number of recorded problems.
+    pub fn count(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn counts(&self) -> &BTreeMap<&'static str, usize> {
+        &self.counts
+    }
+
+    /// A one line summary like `3 diagnostics (1 duplicate-start, 2 unknown-activity)`.
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(category, count)| format!("{} {}", count, category))
+            .collect();
+        if parts.is_empty() {
+            "0 diagnostics".to_string()
+        } else {
+            format!("{} diagnostics ({})", self.count(), parts.join(", "))
+        }
+    }
+
+    pub fn finish(&mut self) {
+        let summary = self.summary();
+        if let Some(output) = self.output.as_mut() {
+            let _ = writeln!(output, "logone: {}", summary);
+            let _ = output.flush();
+        }
+    }
+}
+
+pub fn format_diagnostic(error: &LogoneError) -> String {
+    let kind = error.kind();
+    let mut formatted = match error.line() {
+        Some(line) => format!("line {}: {}: {}", line, kind.category(), kind),
+        None => format!("{}: {}", kind.category(), kind),
+    };
+    if let Some(raw) = error.raw() {
+        formatted.push_str(&format!("\n    {}", raw));
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_summary() {
+        let mut diagnostics = Diagnostics::default();
+        assert_eq!(diagnostics.summary(), "0 diagnostics");
+        diagnostics.record(&LogoneError::UnknownStatusId(7));
+        diagnostics.record(&LogoneError::DuplicateStart(1).at_line(2, "@nix {}"));
+        diagnostics.record(&LogoneError::UnknownStatusId(8));
+        assert_eq!(diagnostics.count(), 3);
+        assert_eq!(
+            diagnostics.summary(),
+            "3 diagnostics (1 duplicate-start, 2 unknown-status-id)"
+        );
+    }
+
+    #[test]
+    fn format() {
+        let error = LogoneError::UnknownActivity {
+            id: 5,
+            action: "stop",
+        };
+        assert_eq!(
+            format_diagnostic(&error),
+            "unknown-activity: no activity with id 5 was started, got stop"
+        );
+        assert_eq!(
+            format_diagnostic(&error.at_line(12, "@nix {\"action\":\"stop\",\"id\":5}")),
+            "line 12: unknown-activity: no activity with id 5 was started, got stop\n    @nix {\"action\":\"stop\",\"id\":5}"
+        );
+    }
+}
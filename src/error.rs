@@ -0,0 +1,128 @@
+use crate::logone::Id;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, LogoneError>;
+
+#[derive(Debug, Error)]
+pub enum LogoneError {
+    #[error("invalid JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+
+    #[error("expected a JSON object")]
+    NotAnObject,
+
+    #[error("missing or invalid '{field}' in {action}")]
+    MissingField {
+        field: &'static str,
+        action: &'static str,
+    },
+
+    #[error("expected {expected} fields in {action}, got {found}")]
+    FieldCount {
+        action: &'static str,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("no activity with id {id} was started, got {action}")]
+    UnknownActivity { id: Id, action: &'static str },
+
+    #[error("unknown status id {0}")]
+    UnknownStatusId(Id),
+
+    #[error("activity {0} was started twice")]
+    DuplicateStart(Id),
+
+    #[error("unknown message: action '{action}' with type {message_type:?}")]
+    UnknownMessage {
+        action: String,
+        message_type: Option<u64>,
+    },
+
+    #[error("@cargo protocol violation for id {id}: {violation}")]
+    CargoProtocol { id: Id, violation: &'static str },
+
+    #[error("internal state lock was poisoned")]
+    LockPoisoned,
+
+    /// Wraps any of the above with the input line it was raised for.
+    #[error("line {line}: {source}")]
+    Line {
+        line: usize,
+        raw: String,
+        #[source]
+        source: Box<LogoneError>,
+    },
+}
+
+impl LogoneError {
+    pub fn at_line(self, line: usize, raw: &str) -> Self {
+        match self {
+            LogoneError::Line { .. } => self,
+            _ => LogoneError::Line {
+                line,
+                raw: raw.to_string(),
+                source: Box::new(self),
+            },
+        }
+    }
+
+    /// 1-based number of the offending input line, if known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            LogoneError::Line { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// The offending input line as it was read, if known.
+    pub fn raw(&self) -> Option<&str> {
+        match self {
+            LogoneError::Line { raw, .. } => Some(raw),
+            _ => None,
+        }
+    }
+
+    /// The error without its line information.
+    pub fn kind(&self) -> &LogoneError {
+        match self {
+            LogoneError::Line { source, .. } => source.kind(),
+            _ => self,
+        }
+    }
+
+    /// Short, stable name used to group diagnostics.
+    pub fn category(&self) -> &'static str {
+        match self {
+            LogoneError::InvalidJson(_) => "invalid-json",
+            LogoneError::NotAnObject => "not-an-object",
+            LogoneError::MissingField { .. } => "missing-field",
+            LogoneError::FieldCount { .. } => "field-count",
+            LogoneError::UnknownActivity { .. } => "unknown-activity",
+            LogoneError::UnknownStatusId(_) => "unknown-status-id",
+            LogoneError::DuplicateStart(_) => "duplicate-start",
+            LogoneError::UnknownMessage { .. } => "unknown-message",
+            LogoneError::CargoProtocol { .. } => "cargo-protocol",
+            LogoneError::LockPoisoned => "lock-poisoned",
+            LogoneError::Line { source, .. } => source.category(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_information() {
+        let error = LogoneError::UnknownStatusId(7).at_line(3, "@nix {}");
+        assert_eq!(error.line(), Some(3));
+        assert_eq!(error.raw(), Some("@nix {}"));
+        assert_eq!(error.category(), "unknown-status-id");
+        assert!(matches!(error.kind(), LogoneError::UnknownStatusId(7)));
+        // the first line a problem was raised for is kept
+        let error = error.at_line(4, "@nix []");
+        assert_eq!(error.line(), Some(3));
+        assert_eq!(error.to_string(), "line 3: unknown status id 7");
+    }
+}
@@ -0,0 +1,330 @@
+use crate::logone::Id;
+use crate::reports::{self, Outcome};
+use crate::rustc::{workspace_path, RustcDiagnostic};
+use crate::sinks::cargo_logs::{CargoSession, CompilerArtifact, ExitStatus};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+pub type EventHandler = Box<dyn FnMut(&Event)>;
+
+/// Version of the JSON of `Event::to_json`, increased on incompatible changes.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// What logone made of the input, for library consumers who want more than the
+/// terminal output. Register a handler with `LogOne::on_event`.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// a build activity (type 105) started
+    BuildStarted { id: Id, drv: String },
+    /// a build entered a phase (result type 104)
+    Phase { id: Id, phase: String },
+    /// a line of build output (result type 101), except `@cargo` messages
+    LogLine { id: Id, line: String },
+    /// a build activity stopped, sent once it is known whether nix reported it as failed
+    BuildFinished {
+        id: Id,
+        drv: String,
+        outcome: Outcome,
+        exit_status: Option<ExitStatus>,
+        duration: Option<Duration>,
+    },
+    /// the status counters of nix (result type 105 of the type 104 activity)
+    Stats {
+        done: u64,
+        expected: u64,
+        running: u64,
+        failed: u64,
+    },
+    /// an error of nix which is not about a derivation, e.g. from the evaluation
+    EvalError {
+        message: String,
+        file: Option<String>,
+        line: Option<u64>,
+        column: Option<u64>,
+    },
+    /// an input line which is not an `@nix` message, unless `OtherLines::Drop`
+    OtherLine { line: String },
+    /// a `@cargo` type 0 started a session
+    CrateCompiling { id: Id, session: CargoSession },
+    /// a `@cargo` type 1 message of an active session
+    CrateMessage { id: Id, message: String },
+    /// a `@cargo` type 2 or 3 ended a session
+    CrateFinished { id: Id, session: CargoSession },
+    /// the files a type 2 end reported, sent after its `CrateFinished`
+    CrateArtifact { id: Id, artifact: CompilerArtifact },
+    /// a rustc diagnostic of a type 2 end, sent before its `CrateFinished`
+    Diagnostic {
+        id: Id,
+        crate_name: String,
+        crate_type: String,
+        drv: Option<String>,
+        diagnostic: RustcDiagnostic,
+    },
+}
+
+fn exit_fields(event: &mut Value, exit_status: Option<ExitStatus>) {
+    let (code, signal) = match exit_status {
+        Some(ExitStatus::Code(code)) => (json!(code), Value::Null),
+        Some(ExitStatus::Signal(signal)) => (Value::Null, json!(signal)),
+        _ => (Value::Null, Value::Null),
+    };
+    event["exit_code"] = code;
+    event["signal"] = signal;
+}
+
+fn seconds(duration: Option<Duration>) -> Value {
+    json!(duration.map(|duration| reports::seconds(Some(duration))))
+}
+
+fn session_fields(event: &mut Value, session: &CargoSession) {
+    event["crate"] = json!(session.crate_name);
+    event["target"] = json!(session.crate_type);
+    event["version"] = json!(session.crate_version);
+    event["drv"] = json!(session.derivation);
+}
+
+impl Event {
+    /// The event in the stable, versioned JSON of `--output ndjson`:
+    /// `{"v":1,"event":"build_started","time":"...",...}`.
+    pub fn to_json(&self) -> Value {
+        let (name, mut event) = match self {
+            Event::BuildStarted { id, drv } => (
+                "build_started",
+                json!({ "id": id, "drv": drv, "name": crate::logone::derivation_name(drv) }),
+            ),
+            Event::Phase { id, phase } => ("phase", json!({ "id": id, "phase": phase })),
+            Event::LogLine { id, line } => ("log_line", json!({ "id": id, "line": line })),
+            Event::BuildFinished {
+                id,
+                drv,
+                outcome,
+                exit_status,
+                duration,
+            } => {
+                let mut event = json!({
+                    "id": id,
+                    "drv": drv,
+                    "name": crate::logone::derivation_name(drv),
+                    "status": outcome.name(),
+                    "duration": seconds(*duration),
+                });
+                exit_fields(&mut event, *exit_status);
+                ("build_finished", event)
+            }
+            Event::Stats {
+                done,
+                expected,
+                running,
+                failed,
+            } => (
+                "stats",
+                json!({ "done": done, "expected": expected, "running": running, "failed": failed }),
+            ),
+            Event::EvalError {
+                message,
+                file,
+                line,
+                column,
+            } => (
+                "eval_error",
+                json!({ "message": message, "file": file, "line": line, "column": column }),
+            ),
+            Event::OtherLine { line } => ("other_line", json!({ "line": line })),
+            Event::CrateCompiling { id, session } => {
+                let mut event = json!({ "id": id });
+                session_fields(&mut event, session);
+                ("crate_compiling", event)
+            }
+            Event::CrateMessage { id, message } => {
+                ("crate_message", json!({ "id": id, "message": message }))
+            }
+            Event::CrateFinished { id, session } => {
+                let outcome = match session.exit_status {
+                    Some(status) if status.success() => Outcome::Success,
+                    _ => Outcome::Failure,
+                };
+                let duration = session.finished.map(|finished| finished - session.started);
+                let mut event = json!({
+                    "id": id,
+                    "status": outcome.name(),
+                    "duration": seconds(duration),
+                });
+                session_fields(&mut event, session);
+                exit_fields(&mut event, session.exit_status);
+                ("crate_finished", event)
+            }
+            Event::CrateArtifact { id, artifact } => (
+                "crate_artifact",
+                json!({
+                    "id": id,
+                    "package_id": artifact.package_id,
+                    "target": artifact.target,
+                    "filenames": artifact.filenames,
+                }),
+            ),
+            Event::Diagnostic {
+                id,
+                crate_name,
+                crate_type,
+                drv,
+                diagnostic,
+            } => {
+                let span = diagnostic.primary_span();
+                let event = json!({
+                    "id": id,
+                    "level": diagnostic.level,
+                    "code": diagnostic.code(),
+                    "message": diagnostic.message,
+                    "file": span.map(|span| workspace_path(&span.file_name)),
+                    "line": span.map(|span| span.line_start),
+                    "column": span.map(|span| span.column_start),
+                    "rendered": diagnostic.rendered,
+                    "crate": crate_name,
+                    "target": crate_type,
+                    "drv": drv,
+                });
+                ("diagnostic", event)
+            }
+        };
+        event["v"] = json!(SCHEMA_VERSION);
+        event["event"] = json!(name);
+        event["time"] = json!(Utc::now().to_rfc3339());
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{rustc_artifact, rustc_diagnostic, Generator};
+    use crate::logone::{LogLevel, LogOne, OtherLines};
+    use crate::parser::parse_nix_line;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    // the field names of the first event of each kind
+    fn fields(events: &[Value]) -> BTreeMap<String, Vec<String>> {
+        let mut fields = BTreeMap::new();
+        for event in events {
+            let name = event["event"].as_str().unwrap().to_string();
+            let keys = event.as_object().unwrap().keys().cloned().collect();
+            fields.entry(name).or_insert(keys);
+        }
+        fields
+    }
+
+    #[test]
+    fn schema() {
+        let mut gen = Generator::new(149);
+        gen.raw("$ nix build .#quote");
+        let build = gen.build_start("quote-1_0_38-8f6d2a1c9b0e7d34");
+        gen.phase(build.id, "buildPhase");
+        gen.log_line(build.id, "Running phase: buildPhase");
+        gen.cargo_start(build.id, "quote", "(lib)");
+        gen.cargo_message(build.id, "quote", "(lib)", &["Compiling quote v1.0.38"]);
+        let warning = rustc_diagnostic("warning", Some("unused"), "unused", "src/lib.rs", 3, 5);
+        let artifact = rustc_artifact("/nix/store/0aa-quote/lib/libquote.rlib", "link");
+        gen.cargo_rustc_exit(build.id, "quote", "(lib)", 0, vec![warning, artifact]);
+        gen.stop(build.id);
+        gen.build_failed(&build, 2, &[]);
+        gen.eval_error("/home/user/flake.nix", 3, 5, "undefined variable 'x'");
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let mut logone = LogOne::new(false, LogLevel::Cargo)
+            .with_other_lines(OtherLines::Collect)
+            .with_event_handler(move |event| sink.borrow_mut().push(event.to_json()));
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        logone.shutdown();
+        let mut events = events.take();
+        events.push(
+            Event::Stats {
+                done: 1,
+                expected: 2,
+                running: 1,
+                failed: 0,
+            }
+            .to_json(),
+        );
+
+        for event in &events {
+            assert_eq!(event["v"], json!(SCHEMA_VERSION));
+            assert!(event["time"].is_string());
+        }
+        let common = ["event", "time", "v"];
+        let expected: BTreeMap<String, Vec<String>> = [
+            (
+                "build_finished",
+                &[
+                    "drv",
+                    "duration",
+                    "exit_code",
+                    "id",
+                    "name",
+                    "signal",
+                    "status",
+                ][..],
+            ),
+            ("build_started", &["drv", "id", "name"]),
+            (
+                "crate_artifact",
+                &["filenames", "id", "package_id", "target"],
+            ),
+            (
+                "crate_compiling",
+                &["crate", "drv", "id", "target", "version"],
+            ),
+            (
+                "crate_finished",
+                &[
+                    "crate",
+                    "drv",
+                    "duration",
+                    "exit_code",
+                    "id",
+                    "signal",
+                    "status",
+                    "target",
+                    "version",
+                ],
+            ),
+            ("crate_message", &["id", "message"]),
+            (
+                "diagnostic",
+                &[
+                    "code", "column", "crate", "drv", "file", "id", "level", "line", "message",
+                    "rendered", "target",
+                ],
+            ),
+            ("eval_error", &["column", "file", "line", "message"]),
+            ("log_line", &["id", "line"]),
+            ("other_line", &["line"]),
+            ("phase", &["id", "phase"]),
+            ("stats", &["done", "expected", "failed", "running"]),
+        ]
+        .into_iter()
+        .map(|(name, keys)| {
+            let mut keys: Vec<String> = keys
+                .iter()
+                .chain(&common)
+                .map(|key| key.to_string())
+                .collect();
+            keys.sort();
+            (name.to_string(), keys)
+        })
+        .collect();
+        assert_eq!(fields(&events), expected);
+
+        let finished = events
+            .iter()
+            .find(|event| event["event"] == "build_finished")
+            .unwrap();
+        assert_eq!(finished["name"], "quote-1_0_38-8f6d2a1c9b0e7d34");
+        assert_eq!(finished["status"], "failure");
+        assert_eq!(finished["exit_code"], 2);
+        assert!(finished["duration"].is_f64());
+    }
+}
@@ -0,0 +1,131 @@
+use regex::Regex;
+
+/// Rules on top of the `LogLevel`, set with `LogOne::with_filter`.
+///
+/// Derivations are matched by their name, i.e. without `/nix/store/<hash>-` and `.drv`.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include_derivations: Vec<Regex>,
+    exclude_derivations: Vec<Regex>,
+    hide_messages: Vec<Regex>,
+    show_levels: Vec<u64>,
+    hide_levels: Vec<u64>,
+    always_show: Vec<Regex>,
+}
+
+impl Filter {
+    /// Only derivations matching one of the included patterns are shown, once there is one.
+    pub fn include_derivation(mut self, pattern: Regex) -> Self {
+        self.include_derivations.push(pattern);
+        self
+    }
+
+    /// Logs and `@cargo` sessions of matching derivations are dropped.
+    pub fn exclude_derivation(mut self, pattern: Regex) -> Self {
+        self.exclude_derivations.push(pattern);
+        self
+    }
+
+    /// Messages and build log lines matching the pattern are dropped,
+    /// e.g. `^trace: Using Cargo.dependencies.nix`.
+    pub fn hide_message(mut self, pattern: Regex) -> Self {
+        self.hide_messages.push(pattern);
+        self
+    }
+
+    /// Prints nix messages of the verbosity level (0 error ... 7 vomit) in every `LogLevel`.
+    pub fn show_level(mut self, level: u64) -> Self {
+        self.show_levels.push(level);
+        self
+    }
+
+    /// Never prints nix messages of the verbosity level.
+    pub fn hide_level(mut self, level: u64) -> Self {
+        self.hide_levels.push(level);
+        self
+    }
+
+    /// Prints the build log of matching derivations even when they succeed.
+    pub fn always_show(mut self, pattern: Regex) -> Self {
+        self.always_show.push(pattern);
+        self
+    }
+
+    pub fn shows_derivation(&self, name: &str) -> bool {
+        let included = self.include_derivations.is_empty()
+            || self.include_derivations.iter().any(|re| re.is_match(name));
+        included && !self.exclude_derivations.iter().any(|re| re.is_match(name))
+    }
+
+    pub fn hides_message(&self, text: &str) -> bool {
+        self.hide_messages.iter().any(|re| re.is_match(text))
+    }
+
+    /// Whether a nix message of `level` is printed, `default` being what the `LogLevel` does.
+    pub fn shows_level(&self, level: u64, default: bool) -> bool {
+        if self.hide_levels.contains(&level) {
+            false
+        } else {
+            default || self.show_levels.contains(&level)
+        }
+    }
+
+    pub fn always_shows(&self, name: &str) -> bool {
+        self.always_show.iter().any(|re| re.is_match(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn re(pattern: &str) -> Regex {
+        Regex::new(pattern).unwrap()
+    }
+
+    #[test]
+    fn derivations() {
+        let filter = Filter::default();
+        assert!(filter.shows_derivation("hello-2.12.1"));
+
+        let filter = Filter::default()
+            .include_derivation(re("^serde"))
+            .include_derivation(re("^syn-"))
+            .exclude_derivation(re("-script_build_run-"));
+        assert!(filter.shows_derivation("serde-1_0_218-472e28b9f131b02c"));
+        assert!(filter.shows_derivation("syn-2_0_98-93aa0f13dad61a07"));
+        assert!(!filter.shows_derivation("hello-2.12.1"));
+        // excluding wins over including
+        assert!(!filter.shows_derivation("serde-1_0_218-script_build_run-00cb1c0a8601ad49"));
+
+        let filter = Filter::default().exclude_derivation(re("^hello"));
+        assert!(!filter.shows_derivation("hello-2.12.1"));
+        assert!(filter.shows_derivation("serde-1_0_218-472e28b9f131b02c"));
+    }
+
+    #[test]
+    fn levels() {
+        let filter = Filter::default()
+            .show_level(5)
+            .show_level(6)
+            .hide_level(6)
+            .hide_level(1);
+        assert!(filter.shows_level(5, false));
+        assert!(filter.shows_level(0, true));
+        assert!(!filter.shows_level(4, false));
+        // hiding wins over showing and over the log level
+        assert!(!filter.shows_level(6, false));
+        assert!(!filter.shows_level(1, true));
+    }
+
+    #[test]
+    fn messages() {
+        let filter = Filter::default()
+            .hide_message(re("^trace: Using Cargo"))
+            .always_show(re("^hello-"));
+        assert!(filter.hides_message("trace: Using Cargo.dependencies.nix"));
+        assert!(!filter.hides_message("warning: unused variable"));
+        assert!(filter.always_shows("hello-2.12.1"));
+        assert!(!filter.always_shows("bash-5.2p37"));
+    }
+}
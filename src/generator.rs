@@ -0,0 +1,703 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::logone::Id;
+
+// nix uses its own base32 alphabet for store path hashes
+const NIX_BASE32: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+#[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
+pub enum Scenario {
+    /// several derivations building in parallel, all successful
+    Parallel,
+    /// parallel builds where one derivation fails with an exit code
+    Failure,
+    /// a nix evaluation error, nothing gets built
+    EvalError,
+    /// store paths substituted from a binary cache
+    Substitution,
+    /// interleaved @cargo type 0/1/2/3 sessions inside nix builds
+    Cargo,
+    /// activities and @cargo sessions which never receive a stop/end
+    MissingStop,
+}
+
+/// A started `type 105` build activity.
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub id: Id,
+    pub name: String,
+    pub drv: String,
+}
+
+/// Produces `@nix` / `@cargo` lines as `nix build --log-format internal-json` would.
+///
+/// All randomness (store hashes, interleaving) is derived from `seed`, so the same
+/// seed always produces the same stream.
+pub struct Generator {
+    next_id: Id,
+    rng: u64,
+    lines: Vec<String>,
+}
+
+impl Generator {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // nix activity ids are (pid << 32) + counter, the pid differs per seed so
+            // streams of different seeds don't share ids
+            next_id: ((39_304 + seed % 32_768) << 32) + 1,
+            rng: seed ^ 0x9e37_79b9_7f4a_7c15,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn into_lines(self) -> Vec<String> {
+        self.lines
+    }
+
+    // xorshift64*, good enough for shuffling test streams
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn pick(&mut self, n: usize) -> usize {
+        (self.next_random() % n as u64) as usize
+    }
+
+    fn next_id(&mut self) -> Id {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn store_path(&mut self, name: &str) -> String {
+        let hash: String = (0..32)
+            .map(|_| NIX_BASE32[self.pick(NIX_BASE32.len())] as char)
+            .collect();
+        format!("/nix/store/{}-{}", hash, name)
+    }
+
+    pub fn raw(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+    }
+
+    fn nix(&mut self, value: Value) {
+        self.lines.push(format!("@nix {}", value));
+    }
+
+    pub fn activity_start(
+        &mut self,
+        activity_type: u64,
+        level: u64,
+        parent: Id,
+        text: &str,
+        fields: Option<Value>,
+    ) -> Id {
+        let id = self.next_id();
+        let mut value = json!({
+            "action": "start",
+            "id": id,
+            "level": level,
+            "parent": parent,
+            "text": text,
+            "type": activity_type,
+        });
+        if let Some(fields) = fields {
+            value["fields"] = fields;
+        }
+        self.nix(value);
+        id
+    }
+
+    pub fn result(&mut self, id: Id, result_type: u64, fields: Value) {
+        self.nix(json!({ "action": "result", "fields": fields, "id": id, "type": result_type }));
+    }
+
+    pub fn stop(&mut self, id: Id) {
+        self.nix(json!({ "action": "stop", "id": id }));
+    }
+
+    pub fn msg(&mut self, level: u64, msg: &str) {
+        self.nix(json!({ "action": "msg", "level": level, "msg": msg }));
+    }
+
+    /// Starts the `type 104` activity which carries the `[ done, expected, running, failed ]` counters.
+    pub fn status_start(&mut self) -> Id {
+        self.activity_start(104, 0, 0, "", None)
+    }
+
+    pub fn status_update(&mut self, id: Id, done: u64, expected: u64, running: u64, failed: u64) {
+        self.result(id, 105, json!([done, expected, running, failed]));
+    }
+
+    pub fn build_start(&mut self, name: &str) -> Build {
+        let drv = format!("{}.drv", self.store_path(name));
+        let text = format!("building '{}'", drv);
+        let id = self.activity_start(105, 3, 0, &text, Some(json!([drv, "", 1, 1])));
+        Build {
+            id,
+            name: name.to_string(),
+            drv,
+        }
+    }
+
+    pub fn phase(&mut self, id: Id, phase: &str) {
+        self.log_line(id, &format!("Running phase: {}", phase));
+        self.result(id, 104, json!([phase]));
+    }
+
+    pub fn log_line(&mut self, id: Id, line: &str) {
+        self.result(id, 101, json!([line]));
+    }
+
+    /// The level 0 error nix prints after a builder exited with a non-zero code.
+    pub fn build_failed(&mut self, build: &Build, exit_code: i64, last_lines: &[&str]) {
+        let mut msg = format!(
+            "\x1b[31;1merror:\x1b[0m builder for '\x1b[35;1m{}\x1b[0m' failed with exit code {};\n       last {} log lines:\n",
+            build.drv,
+            exit_code,
+            last_lines.len()
+        );
+        for line in last_lines {
+            msg.push_str(&format!("       > {}\n", line));
+        }
+        msg.push_str(&format!(
+            "       For full logs, run:\n         \x1b[1mnix log {}\x1b[0m",
+            build.drv
+        ));
+        self.nix(json!({
+            "action": "msg",
+            "column": null,
+            "file": null,
+            "level": 0,
+            "line": null,
+            "msg": msg,
+        }));
+    }
+
+    pub fn eval_error(&mut self, file: &str, line: u64, column: u64, error: &str) {
+        let position = format!("{}:{}:{}", file, line, column);
+        let msg = format!(
+            "\x1b[31;1merror:\x1b[0m {}\n       \x1b[34;1mat \x1b[35;1m{}\x1b[0m:",
+            error, position
+        );
+        self.nix(json!({
+            "action": "msg",
+            "column": column,
+            "file": position,
+            "level": 0,
+            "line": line,
+            "msg": msg,
+        }));
+    }
+
+    /// A `type 108` substitution with a nested `type 101` file transfer.
+    pub fn substitute(&mut self, name: &str, cache: &str, size: u64) {
+        let path = self.store_path(name);
+        let text = format!("copying path '{}' from '{}'", path, cache);
+        let substitution = self.activity_start(108, 4, 0, &text, Some(json!([path, cache])));
+        let hash = &path["/nix/store/".len().."/nix/store/".len() + 32];
+        let url = format!("{}/nar/{}.nar.xz", cache, hash);
+        let transfer = self.activity_start(
+            101,
+            4,
+            substitution,
+            &format!("downloading '{}'", url),
+            Some(json!([url])),
+        );
+        self.result(transfer, 105, json!([size / 2, size, 0, 0]));
+        self.result(transfer, 105, json!([size, size, 0, 0]));
+        self.stop(transfer);
+        self.stop(substitution);
+    }
+
+    fn cargo(&mut self, id: Id, message: Value) {
+        self.log_line(id, &format!("@cargo {}", message));
+    }
+
+    pub fn cargo_start(&mut self, id: Id, crate_name: &str, crate_type: &str) {
+        self.cargo(
+            id,
+            json!({ "type": 0, "crate_name": crate_name, "crate_type": crate_type }),
+        );
+    }
+
+    pub fn cargo_message(&mut self, id: Id, crate_name: &str, crate_type: &str, messages: &[&str]) {
+        self.cargo(
+            id,
+            json!({
+                "type": 1,
+                "crate_name": crate_name,
+                "crate_type": crate_type,
+                "messages": messages,
+            }),
+        );
+    }
+
+    pub fn cargo_rustc_exit(
+        &mut self,
+        id: Id,
+        crate_name: &str,
+        crate_type: &str,
+        rustc_exit_code: i64,
+        rustc_messages: Vec<Value>,
+    ) {
+        self.cargo(
+            id,
+            json!({
+                "type": 2,
+                "crate_name": crate_name,
+                "crate_type": crate_type,
+                "rustc_exit_code": rustc_exit_code,
+                "rustc_messages": rustc_messages,
+            }),
+        );
+    }
+
+    pub fn cargo_build_exit(
+        &mut self,
+        id: Id,
+        crate_name: &str,
+        crate_type: &str,
+        exit_code: i64,
+        messages: &[&str],
+    ) {
+        self.cargo(
+            id,
+            json!({
+                "type": 3,
+                "crate_name": crate_name,
+                "crate_type": crate_type,
+                "exit_code": exit_code,
+                "messages": messages,
+            }),
+        );
+    }
+
+    /// Emits the bodies of several builds line by line in a seeded, interleaved order.
+    pub fn interleave(&mut self, mut bodies: Vec<(Id, Vec<String>)>) {
+        while !bodies.is_empty() {
+            let i = self.pick(bodies.len());
+            let (id, lines) = &mut bodies[i];
+            let id = *id;
+            let line = lines.remove(0);
+            if lines.is_empty() {
+                bodies.remove(i);
+            }
+            self.log_line(id, &line);
+        }
+    }
+}
+
+pub fn rustc_diagnostic(
+    level: &str,
+    code: Option<&str>,
+    message: &str,
+    file: &str,
+    line: u64,
+    column: u64,
+) -> Value {
+    let rendered = format!(
+        "{}{}: {}\n  --> {}:{}:{}\n\n",
+        level,
+        code.map(|c| format!("[{}]", c)).unwrap_or_default(),
+        message,
+        file,
+        line,
+        column
+    );
+    json!({
+        "$message_type": "diagnostic",
+        "message": message,
+        "code": code.map(|c| json!({ "code": c, "explanation": null })),
+        "level": level,
+        "spans": [{
+            "file_name": file,
+            "byte_start": 0,
+            "byte_end": 1,
+            "line_start": line,
+            "line_end": line,
+            "column_start": column,
+            "column_end": column + 1,
+            "is_primary": true,
+            "text": [],
+            "label": null,
+            "suggested_replacement": null,
+            "suggestion_applicability": null,
+            "expansion": null,
+        }],
+        "children": [],
+        "rendered": rendered,
+    })
+}
+
+pub fn rustc_artifact(path: &str, emit: &str) -> Value {
+    json!({ "$message_type": "artifact", "artifact": path, "emit": emit })
+}
+
+const CRATES: &[&str] = &[
+    "proc-macro2-1_0_93",
+    "unicode-ident-1_0_17",
+    "quote-1_0_38",
+    "syn-2_0_98",
+    "serde-1_0_218",
+    "memchr-2_7_4",
+    "libc-0_2_170",
+];
+
+// more parallel builds than CRATES get numbered copies, e.g. "serde-1_0_218-2"
+fn crate_name(n: usize) -> String {
+    let name = CRATES[n % CRATES.len()];
+    match n / CRATES.len() {
+        0 => name.to_string(),
+        copy => format!("{}-{}", name, copy + 1),
+    }
+}
+
+fn build_body(name: &str, lines: usize) -> Vec<String> {
+    (0..lines)
+        .map(|n| format!("{}: compiling object {} of {}", name, n + 1, lines))
+        .collect()
+}
+
+fn parallel_builds(gen: &mut Generator, count: usize, failing: Option<usize>) {
+    let status = gen.status_start();
+    let expected = count as u64;
+    gen.status_update(status, 0, expected, 0, 0);
+
+    let builds: Vec<Build> = (0..count)
+        .map(|n| gen.build_start(&crate_name(n)))
+        .collect();
+    gen.status_update(status, 0, expected, expected, 0);
+    for build in &builds {
+        gen.phase(build.id, "unpackPhase");
+        gen.log_line(build.id, &format!("unpacking source archive {}", build.drv));
+        gen.phase(build.id, "buildPhase");
+    }
+    let bodies = builds
+        .iter()
+        .map(|build| (build.id, build_body(&build.name, 4)))
+        .collect();
+    gen.interleave(bodies);
+
+    let (mut done, mut failed) = (0, 0);
+    for (n, build) in builds.iter().enumerate() {
+        if failing == Some(n) {
+            gen.log_line(build.id, "make: *** [Makefile:12: all] Error 2");
+            gen.stop(build.id);
+            failed += 1;
+            gen.status_update(status, done, expected, expected - done - failed, failed);
+            gen.build_failed(
+                build,
+                2,
+                &[
+                    "Running phase: buildPhase",
+                    "make: *** [Makefile:12: all] Error 2",
+                ],
+            );
+        } else {
+            gen.phase(build.id, "installPhase");
+            gen.stop(build.id);
+            done += 1;
+            gen.status_update(status, done, expected, expected - done - failed, failed);
+        }
+    }
+    gen.stop(status);
+}
+
+fn cargo_sessions(gen: &mut Generator) {
+    let status = gen.status_start();
+    gen.status_update(status, 0, 3, 0, 0);
+
+    let lib = gen.build_start("memchr-2_7_4-3cee6db17bbe0dde");
+    let build_rs = gen.build_start("libc-0_2_170-script_build_run-00cb1c0a8601ad49");
+    let broken = gen.build_start("cargo-credential-0_4_8-a10e20e8704a5f47");
+    gen.status_update(status, 0, 3, 3, 0);
+
+    gen.phase(lib.id, "buildPhase");
+    gen.cargo_start(lib.id, "memchr", "(lib)");
+    gen.phase(build_rs.id, "buildPhase");
+    gen.cargo_start(build_rs.id, "libc", "(build.rs run)");
+    gen.phase(broken.id, "buildPhase");
+    gen.cargo_start(broken.id, "cargo-credential", "(lib)");
+
+    gen.cargo_message(
+        build_rs.id,
+        "libc",
+        "(build.rs run)",
+        &["running build script"],
+    );
+    gen.log_line(build_rs.id, "+++ rustc_exit_value=0");
+    gen.cargo_build_exit(
+        build_rs.id,
+        "libc",
+        "(build.rs run)",
+        0,
+        &[
+            "cargo:rerun-if-changed=build.rs",
+            "cargo:rustc-cfg=freebsd11",
+            "cargo:warning=deprecated target",
+        ],
+    );
+    gen.stop(build_rs.id);
+    gen.status_update(status, 1, 3, 2, 0);
+
+    let out = gen.store_path("memchr-2_7_4-3cee6db17bbe0dde");
+    let warning = rustc_diagnostic(
+        "warning",
+        Some("unused_imports"),
+        "unused import: `core::fmt`",
+        "src/lib.rs",
+        3,
+        5,
+    );
+    let artifact = rustc_artifact(&format!("{}/libmemchr-3cee6db17bbe0dde.rlib", out), "link");
+    gen.cargo_rustc_exit(
+        lib.id,
+        "memchr",
+        "(lib)",
+        0,
+        vec![warning.clone(), artifact],
+    );
+    // the tests of the same crate report the same warning again
+    gen.cargo_start(lib.id, "memchr", "(test)");
+    gen.cargo_rustc_exit(lib.id, "memchr", "(test)", 0, vec![warning]);
+    gen.stop(lib.id);
+    gen.status_update(status, 2, 3, 1, 0);
+
+    let error = rustc_diagnostic(
+        "error",
+        None,
+        "expected one of `!` or `::`, found `#`",
+        "credential/cargo-credential/src/lib.rs",
+        93,
+        1,
+    );
+    gen.cargo_rustc_exit(broken.id, "cargo-credential", "(lib)", 1, vec![error]);
+    gen.stop(broken.id);
+    gen.status_update(status, 2, 3, 0, 1);
+    gen.build_failed(
+        &broken,
+        1,
+        &["Running phase: buildPhase", "+++ rustc_exit_value=1"],
+    );
+    gen.stop(status);
+}
+
+fn missing_stops(gen: &mut Generator) {
+    let status = gen.status_start();
+    gen.status_update(status, 0, 3, 0, 0);
+
+    let finished = gen.build_start("serde-1_0_218-472e28b9f131b02c");
+    let orphan = gen.build_start("syn-2_0_98-93aa0f13dad61a07");
+    let killed = gen.build_start("quote-1_0_38-12b99e3192e30e82");
+    gen.status_update(status, 0, 3, 3, 0);
+
+    gen.phase(finished.id, "buildPhase");
+    gen.cargo_start(finished.id, "serde", "(lib)");
+    gen.phase(orphan.id, "buildPhase");
+    gen.cargo_start(orphan.id, "syn", "(lib)");
+    gen.phase(killed.id, "buildPhase");
+    gen.log_line(killed.id, "quote: compiling object 1 of 4");
+
+    gen.cargo_rustc_exit(finished.id, "serde", "(lib)", 0, vec![]);
+    gen.stop(finished.id);
+    gen.status_update(status, 1, 3, 2, 0);
+
+    // the orphan build stops without its @cargo end message, the other one is never stopped
+    gen.stop(orphan.id);
+    gen.status_update(status, 2, 3, 1, 0);
+
+    // a stop for an id which was never started and an end without a start
+    gen.stop(orphan.id + 1000);
+    gen.cargo_rustc_exit(killed.id, "quote", "(lib)", 0, vec![]);
+}
+
+pub fn generate(scenario: Scenario, seed: u64) -> Vec<String> {
+    let mut gen = Generator::new(seed);
+    match scenario {
+        Scenario::Parallel => parallel_builds(&mut gen, 4, None),
+        Scenario::Failure => parallel_builds(&mut gen, 4, Some(2)),
+        Scenario::EvalError => {
+            gen.msg(
+                1,
+                "\x1b[35;1mwarning:\x1b[0m Git tree '/home/nixos/cargo' is dirty",
+            );
+            gen.eval_error(
+                "/home/nixos/cargo/default.nix",
+                13,
+                1,
+                "syntax error, unexpected '}', expecting '.' or '='",
+            );
+        }
+        Scenario::Substitution => {
+            let status = gen.status_start();
+            gen.msg(
+                3,
+                "these 2 paths will be fetched (1.20 MiB download, 5.10 MiB unpacked):",
+            );
+            gen.substitute(
+                "memchr-2_7_4-3cee6db17bbe0dde",
+                "https://cache.nixos.org",
+                734_003,
+            );
+            gen.substitute(
+                "libc-0_2_170-00cb1c0a8601ad49",
+                "https://cache.nixos.org",
+                524_288,
+            );
+            gen.status_update(status, 0, 1, 0, 0);
+            let build = gen.build_start("hello-2.12.1");
+            gen.status_update(status, 0, 1, 1, 0);
+            gen.phase(build.id, "buildPhase");
+            gen.log_line(build.id, "hello-2.12.1: linking");
+            gen.stop(build.id);
+            gen.status_update(status, 1, 1, 0, 0);
+            gen.stop(status);
+        }
+        Scenario::Cargo => cargo_sessions(&mut gen),
+        Scenario::MissingStop => missing_stops(&mut gen),
+    }
+    gen.into_lines()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logone::{LogLevel, LogOne, LogStatus};
+    use crate::parser::parse_nix_line;
+    use std::collections::HashSet;
+
+    // Feeds the lines through a LogOne like main does. The status activity is left
+    // out: redrawing the status line moves the cursor of the test runner's output.
+    fn replay(lines: &[String], level: LogLevel) -> LogOne {
+        let mut logone = LogOne::new(false, level);
+        let mut status_ids = HashSet::new();
+        for line in lines {
+            let value: Value = serde_json::from_str(line.strip_prefix("@nix ").unwrap()).unwrap();
+            let id = value["id"].as_u64();
+            if value["action"] == "start" && value["type"] == 104 {
+                status_ids.extend(id);
+            }
+            if id.is_some_and(|id| status_ids.contains(&id)) {
+                continue;
+            }
+            // errors are not what these tests are about, main ignores them too
+            let _ = parse_nix_line(line, &mut logone);
+        }
+        logone
+    }
+
+    fn build_id(logone: &LogOne, name: &str) -> Id {
+        logone
+            .drv_to_id
+            .iter()
+            .find(|(text, _)| text.contains(&format!("-{}.drv", name)))
+            .map(|(_, id)| *id)
+            .unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_stream() {
+        assert_eq!(
+            generate(Scenario::Failure, 7),
+            generate(Scenario::Failure, 7)
+        );
+        assert_ne!(
+            generate(Scenario::Failure, 7),
+            generate(Scenario::Failure, 8)
+        );
+    }
+
+    #[test]
+    fn parallel() {
+        let lines = generate(Scenario::Parallel, 1);
+        let logone = replay(&lines, LogLevel::Verbose);
+        assert_eq!(logone.drv_to_id.len(), 4);
+        for id in logone.drv_to_id.values() {
+            assert_eq!(logone.nix_log_buffers_state[id], LogStatus::Stopped);
+            // three phases with their "Running phase" line, the unpack line and the body
+            assert_eq!(logone.nix_log_buffers[id].len(), 11);
+        }
+    }
+
+    #[test]
+    fn more_parallel_builds_than_crate_names() {
+        let mut gen = Generator::new(2);
+        parallel_builds(&mut gen, 10, None);
+        let logone = replay(gen.lines(), LogLevel::Verbose);
+        assert_eq!(logone.drv_to_id.len(), 10);
+        let id = build_id(&logone, "proc-macro2-1_0_93-2");
+        assert_eq!(logone.nix_log_buffers_state[&id], LogStatus::Stopped);
+    }
+
+    #[test]
+    fn failure() {
+        let lines = generate(Scenario::Failure, 3);
+        let logone = replay(&lines, LogLevel::Errors);
+        // the log of the failed build was printed with nix's error, the others are dropped
+        let failed = build_id(&logone, "quote-1_0_38");
+        assert!(!logone.nix_log_buffers.contains_key(&failed));
+        assert_eq!(logone.nix_log_buffers.len(), 3);
+    }
+
+    #[test]
+    fn eval_error() {
+        let lines = generate(Scenario::EvalError, 4);
+        assert_eq!(lines.len(), 2);
+        let logone = replay(&lines, LogLevel::Errors);
+        assert!(logone.drv_to_id.is_empty());
+    }
+
+    #[test]
+    fn substitution() {
+        let lines = generate(Scenario::Substitution, 5);
+        let logone = replay(&lines, LogLevel::Verbose);
+        let id = build_id(&logone, "hello-2.12.1");
+        assert_eq!(logone.nix_log_buffers_state[&id], LogStatus::Stopped);
+    }
+
+    #[test]
+    fn cargo() {
+        let lines = generate(Scenario::Cargo, 6);
+        let logone = replay(&lines, LogLevel::Cargo);
+        let states: Vec<LogStatus> = lines
+            .iter()
+            .filter(|line| line.contains("\"action\":\"start\"") && line.contains("building"))
+            .map(|line| {
+                let value: Value = serde_json::from_str(&line[5..]).unwrap();
+                logone.cargo_log_buffers_state[&value["id"].as_u64().unwrap()]
+            })
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                LogStatus::FinishedWithSuccess,
+                LogStatus::FinishedWithSuccess,
+                LogStatus::FinishedWithError,
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_stop() {
+        let lines = generate(Scenario::MissingStop, 9);
+        let logone = replay(&lines, LogLevel::Cargo);
+        let mut states: Vec<LogStatus> = logone.cargo_log_buffers_state.values().copied().collect();
+        states.sort_by_key(|state| *state as u8);
+        // serde finished, syn's activity stopped before its session got an end message
+        assert_eq!(
+            states,
+            vec![LogStatus::Stopped, LogStatus::FinishedWithSuccess]
+        );
+    }
+}
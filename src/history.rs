@@ -0,0 +1,527 @@
+use crate::logone::LogOne;
+use crate::reports::{builds, seconds, Outcome};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// One run of logone, stored as a line of JSON in the history file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub time: DateTime<Utc>,
+    pub builds: Vec<BuildRecord>,
+}
+
+/// A derivation built in a `Run`, identified by its name as builds of different
+/// versions of it have different store paths.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub name: String,
+    pub drv: String,
+    pub outcome: Outcome,
+    /// in seconds, `None` if the build did not finish
+    pub duration: Option<f64>,
+    /// rustc warnings of the `@cargo` sessions of the derivation, each counted once
+    pub warnings: usize,
+}
+
+impl Run {
+    pub fn from_logone(logone: &LogOne) -> Run {
+        let diagnostics = logone.unique_diagnostics();
+        let builds = builds(logone)
+            .into_iter()
+            .map(|build| {
+                let warnings = diagnostics
+                    .iter()
+                    .filter(|(session, diagnostic)| {
+                        session.derivation.as_deref() == Some(build.drv.as_str())
+                            && diagnostic.is_warning()
+                    })
+                    .count();
+                BuildRecord {
+                    name: build.name,
+                    drv: build.drv,
+                    outcome: build.outcome,
+                    duration: build.duration.map(|duration| seconds(Some(duration))),
+                    warnings,
+                }
+            })
+            .collect();
+        Run {
+            time: Utc::now(),
+            builds,
+        }
+    }
+
+    pub fn build(&self, name: &str) -> Option<&BuildRecord> {
+        self.builds.iter().find(|build| build.name == name)
+    }
+}
+
+/// Appends `run` to the history file, which is created if needed.
+pub fn append(path: &Path, run: &Run) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(run)?)
+}
+
+/// The runs of the history file, oldest first. Lines which can't be read, e.g. the
+/// last one of a logone which was killed while writing, are skipped.
+pub fn load(path: &Path) -> io::Result<Vec<Run>> {
+    let mut runs = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(run) = serde_json::from_str(&line?) {
+            runs.push(run);
+        }
+    }
+    Ok(runs)
+}
+
+/// A derivation which both succeeded and failed in the history.
+#[derive(Debug, Clone)]
+pub struct Flaky {
+    pub name: String,
+    pub runs: usize,
+    pub failures: usize,
+    /// how often the outcome changed from one run to the next it was built in
+    pub flips: usize,
+}
+
+/// Derivations whose outcome changed at least twice, e.g. success, failure, success,
+/// the most changing first.
+pub fn flaky(runs: &[Run]) -> Vec<Flaky> {
+    let mut outcomes: BTreeMap<&str, Vec<Outcome>> = BTreeMap::new();
+    for run in runs {
+        for build in &run.builds {
+            if build.outcome != Outcome::Unfinished {
+                outcomes
+                    .entry(build.name.as_str())
+                    .or_default()
+                    .push(build.outcome);
+            }
+        }
+    }
+    let mut flaky: Vec<Flaky> = outcomes
+        .into_iter()
+        .map(|(name, outcomes)| Flaky {
+            name: name.to_string(),
+            runs: outcomes.len(),
+            failures: outcomes.iter().filter(|&&o| o == Outcome::Failure).count(),
+            flips: outcomes.windows(2).filter(|w| w[0] != w[1]).count(),
+        })
+        .filter(|flaky| flaky.flips >= 2)
+        .collect();
+    flaky.sort_by(|a, b| b.flips.cmp(&a.flips).then(a.name.cmp(&b.name)));
+    flaky
+}
+
+/// What changed for a derivation built in two runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Slower {
+        name: String,
+        before: f64,
+        after: f64,
+    },
+    Faster {
+        name: String,
+        before: f64,
+        after: f64,
+    },
+    Failing {
+        name: String,
+    },
+    Fixed {
+        name: String,
+    },
+    MoreWarnings {
+        name: String,
+        before: usize,
+        after: usize,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |before: f64, after: f64| ((after - before) / before * 100.0).abs().round();
+        match self {
+            Change::Slower {
+                name,
+                before,
+                after,
+            } => write!(
+                f,
+                "{} got {}% slower ({:.1}s -> {:.1}s)",
+                name,
+                percent(*before, *after),
+                before,
+                after
+            ),
+            Change::Faster {
+                name,
+                before,
+                after,
+            } => write!(
+                f,
+                "{} got {}% faster ({:.1}s -> {:.1}s)",
+                name,
+                percent(*before, *after),
+                before,
+                after
+            ),
+            Change::Failing { name } => write!(f, "{} fails now", name),
+            Change::Fixed { name } => write!(f, "{} builds again", name),
+            Change::MoreWarnings {
+                name,
+                before,
+                after,
+            } => write!(f, "{} has {} warnings instead of {}", name, after, before),
+        }
+    }
+}
+
+/// Compares the derivations built in both runs. Durations count as changed when
+/// they differ by more than `threshold` percent and one of them is at least
+/// `min_seconds`, shorter builds vary too much.
+pub fn compare(previous: &Run, current: &Run, threshold: f64, min_seconds: f64) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for build in &current.builds {
+        let before = match previous.build(&build.name) {
+            Some(before) => before,
+            None => continue,
+        };
+        let name = build.name.clone();
+        match (before.outcome, build.outcome) {
+            (Outcome::Success, Outcome::Failure) => changes.push(Change::Failing { name }),
+            (Outcome::Failure, Outcome::Success) => changes.push(Change::Fixed { name }),
+            (Outcome::Success, Outcome::Success) => {
+                if let (Some(before), Some(after)) = (before.duration, build.duration) {
+                    let relevant = before.max(after) >= min_seconds && before > 0.0;
+                    let percent = (after - before) / before * 100.0;
+                    if relevant && percent > threshold {
+                        changes.push(Change::Slower {
+                            name: name.clone(),
+                            before,
+                            after,
+                        });
+                    } else if relevant && -percent > threshold {
+                        changes.push(Change::Faster {
+                            name: name.clone(),
+                            before,
+                            after,
+                        });
+                    }
+                }
+                if build.warnings > before.warnings {
+                    changes.push(Change::MoreWarnings {
+                        name,
+                        before: before.warnings,
+                        after: build.warnings,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// Prints the duration and outcome of the derivations matching `pattern` in the
+/// last `limit` runs they were built in, with a bar for the duration.
+pub fn write_trend(
+    runs: &[Run],
+    pattern: &Regex,
+    limit: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut trends: BTreeMap<&str, Vec<(DateTime<Utc>, &BuildRecord)>> = BTreeMap::new();
+    for run in runs {
+        for build in run.builds.iter().filter(|b| pattern.is_match(&b.name)) {
+            trends
+                .entry(build.name.as_str())
+                .or_default()
+                .push((run.time, build));
+        }
+    }
+    if trends.is_empty() {
+        writeln!(out, "no derivation matching '{}' in the history", pattern)?;
+    }
+    for (name, points) in trends {
+        let points = &points[points.len().saturating_sub(limit)..];
+        let longest = points
+            .iter()
+            .filter_map(|(_, build)| build.duration)
+            .fold(0.0, f64::max);
+        writeln!(out, "{}:", name)?;
+        for (time, build) in points {
+            let duration = match build.duration {
+                Some(duration) => format!("{:>8.1}s", duration),
+                None => format!("{:>9}", "-"),
+            };
+            let width = match build.duration {
+                Some(duration) if longest > 0.0 => (duration / longest * 40.0).round() as usize,
+                _ => 0,
+            };
+            let line = format!(
+                "  {}  {:<10} {} {}",
+                time.format("%Y-%m-%d %H:%M"),
+                build.outcome.name(),
+                duration,
+                "#".repeat(width)
+            );
+            writeln!(out, "{}", line.trim_end())?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the flaky derivations of the last `limit` runs.
+pub fn write_flaky(runs: &[Run], limit: usize, out: &mut impl Write) -> io::Result<()> {
+    let flaky = flaky(&runs[runs.len().saturating_sub(limit)..]);
+    if flaky.is_empty() {
+        return writeln!(out, "no flaky derivations in the last {} runs", limit);
+    }
+    for flaky in flaky {
+        writeln!(
+            out,
+            "{}: failed {} of {} builds, changed {} times",
+            flaky.name, flaky.failures, flaky.runs, flaky.flips
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the changes between the last two runs.
+pub fn write_compare(
+    runs: &[Run],
+    threshold: f64,
+    min_seconds: f64,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let (previous, current) = match runs {
+        [.., previous, current] => (previous, current),
+        _ => return writeln!(out, "the history needs at least two runs to compare"),
+    };
+    let changes = compare(previous, current, threshold, min_seconds);
+    if changes.is_empty() {
+        return writeln!(
+            out,
+            "no changes between {} and {}",
+            previous.time.format("%Y-%m-%d %H:%M"),
+            current.time.format("%Y-%m-%d %H:%M")
+        );
+    }
+    for change in changes {
+        writeln!(out, "{}", change)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn run(day: u32, builds: &[(&str, Outcome, Option<f64>, usize)]) -> Run {
+        Run {
+            time: Utc.with_ymd_and_hms(2025, 3, day, 12, 0, 0).unwrap(),
+            builds: builds
+                .iter()
+                .map(|&(name, outcome, duration, warnings)| BuildRecord {
+                    name: name.to_string(),
+                    drv: format!("/nix/store/0aa-{}.drv", name),
+                    outcome,
+                    duration,
+                    warnings,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn flips() {
+        use Outcome::*;
+        let runs = [
+            run(
+                1,
+                &[
+                    ("hello", Success, Some(1.0), 0),
+                    ("bash", Success, Some(9.0), 0),
+                ],
+            ),
+            run(
+                2,
+                &[
+                    ("hello", Failure, Some(1.0), 0),
+                    ("bash", Failure, Some(9.0), 0),
+                ],
+            ),
+            // unfinished builds don't count
+            run(
+                3,
+                &[
+                    ("hello", Unfinished, None, 0),
+                    ("bash", Failure, Some(9.0), 0),
+                ],
+            ),
+            run(4, &[("hello", Success, Some(1.0), 0)]),
+            run(
+                5,
+                &[
+                    ("hello", Failure, Some(1.0), 0),
+                    ("bash", Success, Some(9.0), 0),
+                ],
+            ),
+        ];
+        let flaky = flaky(&runs);
+        assert_eq!(flaky.len(), 2);
+        assert_eq!(
+            (
+                flaky[0].name.as_str(),
+                flaky[0].runs,
+                flaky[0].failures,
+                flaky[0].flips
+            ),
+            ("hello", 4, 2, 3)
+        );
+        assert_eq!(
+            (
+                flaky[1].name.as_str(),
+                flaky[1].runs,
+                flaky[1].failures,
+                flaky[1].flips
+            ),
+            ("bash", 4, 2, 2)
+        );
+        // a single change is a fix or a regression
+        assert!(super::flaky(&runs[..2]).is_empty());
+
+        let mut out = Vec::new();
+        write_flaky(&runs, 3, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "no flaky derivations in the last 3 runs\n"
+        );
+    }
+
+    #[test]
+    fn changes() {
+        use Outcome::*;
+        let previous = run(
+            1,
+            &[
+                ("slower", Success, Some(10.0), 0),
+                ("faster", Success, Some(10.0), 0),
+                ("within-threshold", Success, Some(10.0), 0),
+                ("short", Success, Some(0.2), 0),
+                ("failing", Success, Some(1.0), 0),
+                ("fixed", Failure, Some(1.0), 0),
+                ("warnings", Success, Some(1.0), 1),
+                ("unfinished", Success, Some(1.0), 0),
+            ],
+        );
+        let current = run(
+            2,
+            &[
+                ("slower", Success, Some(15.0), 0),
+                ("faster", Success, Some(5.0), 0),
+                ("within-threshold", Success, Some(11.0), 0),
+                ("short", Success, Some(0.8), 0),
+                ("failing", Failure, Some(1.0), 0),
+                ("fixed", Success, Some(1.0), 0),
+                ("warnings", Success, Some(1.0), 3),
+                ("unfinished", Unfinished, None, 0),
+                ("new", Failure, Some(1.0), 0),
+            ],
+        );
+        let name = |name: &str| name.to_string();
+        assert_eq!(
+            compare(&previous, &current, 20.0, 1.0),
+            vec![
+                Change::Slower {
+                    name: name("slower"),
+                    before: 10.0,
+                    after: 15.0
+                },
+                Change::Faster {
+                    name: name("faster"),
+                    before: 10.0,
+                    after: 5.0
+                },
+                Change::Failing {
+                    name: name("failing")
+                },
+                Change::Fixed {
+                    name: name("fixed")
+                },
+                Change::MoreWarnings {
+                    name: name("warnings"),
+                    before: 1,
+                    after: 3
+                },
+            ]
+        );
+        // without the minimal duration the short build got slower too
+        assert!(
+            compare(&previous, &current, 20.0, 0.0).contains(&Change::Slower {
+                name: name("short"),
+                before: 0.2,
+                after: 0.8
+            })
+        );
+        // and with a lower threshold the one within it
+        assert_eq!(compare(&previous, &current, 5.0, 1.0).len(), 6);
+
+        let changes: Vec<String> = compare(&previous, &current, 20.0, 1.0)
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                "slower got 50% slower (10.0s -> 15.0s)",
+                "faster got 50% faster (10.0s -> 5.0s)",
+                "failing fails now",
+                "fixed builds again",
+                "warnings has 3 warnings instead of 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn trend() {
+        use Outcome::*;
+        let runs = [
+            run(
+                1,
+                &[
+                    ("hello", Success, Some(2.0), 0),
+                    ("bash", Success, Some(9.0), 0),
+                ],
+            ),
+            run(2, &[("hello", Failure, Some(1.0), 0)]),
+            run(3, &[("hello", Unfinished, None, 0)]),
+            run(4, &[("hello", Success, Some(4.0), 0)]),
+        ];
+        let mut out = Vec::new();
+        write_trend(&runs, &Regex::new("^hel").unwrap(), 3, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "hello:\n  2025-03-02 12:00  failure         1.0s {}\n  2025-03-03 12:00  unfinished         -\n  2025-03-04 12:00  success         4.0s {}\n",
+                "#".repeat(10),
+                "#".repeat(40)
+            )
+        );
+
+        let mut out = Vec::new();
+        write_trend(&runs, &Regex::new("zlib").unwrap(), 3, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "no derivation matching 'zlib' in the history\n"
+        );
+    }
+}
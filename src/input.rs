@@ -0,0 +1,288 @@
+use ruzstd::decoding::{FrameDecoder, StreamingDecoder};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Compression of a recorded log, detected from its first bytes rather than the file
+/// name, so compressed input on stdin works too.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    pub fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Longest magic number of `Compression::detect`.
+const MAGIC_LEN: usize = 6;
+
+// reads until there are MAGIC_LEN bytes or the input ends, a pipe can return less
+fn read_magic(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    reader.take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+    Ok(magic)
+}
+
+/// Opens a log for reading lines, `-` being stdin. gzip, zstd and xz compressed logs
+/// are decompressed, xz ones completely before the first line is returned: lzma-rs
+/// has no streaming decoder, so an xz log takes its uncompressed size in memory.
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if is_stdin(path) {
+        decompress(io::stdin().lock())
+    } else {
+        decompress(File::open(path)?)
+    }
+}
+
+fn decompress(mut reader: impl Read + 'static) -> io::Result<Box<dyn BufRead>> {
+    let magic = read_magic(&mut reader)?;
+    let compression = Compression::detect(&magic);
+    let mut reader = BufReader::new(Cursor::new(magic).chain(reader));
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(ZstdFrames {
+            decoder: Some(StreamingDecoder::new(reader).map_err(io::Error::other)?),
+        })),
+        Compression::Xz => {
+            let mut log = Vec::new();
+            lzma_rs::xz_decompress(&mut reader, &mut log).map_err(io::Error::other)?;
+            Box::new(Cursor::new(log))
+        }
+    })
+}
+
+// zstd files can consist of several frames, e.g. when they were appended to
+struct ZstdFrames<R: BufRead> {
+    decoder: Option<StreamingDecoder<R, FrameDecoder>>,
+}
+
+impl<R: BufRead> Read for ZstdFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(decoder) = self.decoder.as_mut() {
+            let read = decoder.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            let mut source = self.decoder.take().map(|d| d.into_inner()).unwrap();
+            if !source.fill_buf()?.is_empty() {
+                self.decoder = Some(StreamingDecoder::new(source).map_err(io::Error::other)?);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// A log which is still being written, read like `tail -f`: at its end, reads wait
+/// for more data until `stop` is set. The file is read from the start, and again
+/// from the start when it is truncated.
+pub struct Follow {
+    file: File,
+    position: u64,
+    stop: Arc<AtomicBool>,
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            if self.file.metadata()?.len() < self.position {
+                self.position = self.file.seek(SeekFrom::Start(0))?;
+                continue;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+// compressed logs can only be read as a whole
+fn check_followable(file: &mut File) -> io::Result<()> {
+    if Compression::detect(&read_magic(file)?) != Compression::None {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "compressed logs can't be followed",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the log at `path` can be read, and followed with `follow`, before
+/// reading starts. stdin always can.
+pub fn check(path: &Path, follow: bool) -> io::Result<()> {
+    if is_stdin(path) {
+        return Ok(());
+    }
+    let mut file = File::open(path)?;
+    if file.metadata()?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "is a directory",
+        ));
+    }
+    if follow {
+        check_followable(&mut file)?;
+    }
+    Ok(())
+}
+
+/// Opens a log to follow, see `Follow`. stdin is read as usual, it waits for more
+/// data anyway, compressed logs can't be followed.
+pub fn follow(path: &Path, stop: Arc<AtomicBool>) -> io::Result<Box<dyn BufRead>> {
+    if is_stdin(path) {
+        return open(path);
+    }
+    let mut file = File::open(path)?;
+    check_followable(&mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(Box::new(BufReader::new(Follow {
+        file,
+        position: 0,
+        stop,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    const LOG: &str = "@nix {\"action\":\"msg\",\"level\":0,\"msg\":\"error: a\"}\n@nix {\"action\":\"msg\",\"level\":0,\"msg\":\"error: b\"}\n";
+
+    // a pipe which returns a byte per read
+    struct Trickle(Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn round_trip(compressed: Vec<u8>, compression: Compression) {
+        assert_eq!(Compression::detect(&compressed), compression);
+        let mut log = String::new();
+        decompress(Trickle(Cursor::new(compressed)))
+            .unwrap()
+            .read_to_string(&mut log)
+            .unwrap();
+        assert_eq!(log, LOG);
+    }
+
+    #[test]
+    fn uncompressed() {
+        round_trip(LOG.as_bytes().to_vec(), Compression::None);
+        // shorter than any magic number
+        let mut log = String::new();
+        decompress(Trickle(Cursor::new(b"@n".to_vec())))
+            .unwrap()
+            .read_to_string(&mut log)
+            .unwrap();
+        assert_eq!(log, "@n");
+    }
+
+    #[test]
+    fn gzip() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(LOG.as_bytes()).unwrap();
+        round_trip(encoder.finish().unwrap(), Compression::Gzip);
+    }
+
+    #[test]
+    fn zstd() {
+        let compressed = ruzstd::encoding::compress_to_vec(
+            LOG.as_bytes(),
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        round_trip(compressed, Compression::Zstd);
+    }
+
+    #[test]
+    fn xz() {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut LOG.as_bytes(), &mut compressed).unwrap();
+        round_trip(compressed, Compression::Xz);
+    }
+
+    #[test]
+    fn follow_truncated() {
+        let path = std::env::temp_dir().join(format!("logone-follow-{}.log", std::process::id()));
+        std::fs::write(&path, "one\ntwo\n").unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut reader = follow(&path, stop.clone()).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "one\ntwo\n");
+
+        // the log is written again, e.g. by the next build
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"three\n").unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "three\n");
+
+        stop.store(true, Ordering::Relaxed);
+        line.clear();
+        assert_eq!(reader.read_line(&mut line).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checks() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("logone-check-{}.log.gz", std::process::id()));
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(LOG.as_bytes()).unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        assert!(check(Path::new("-"), true).is_ok());
+        assert!(check(&path, false).is_ok());
+        assert_eq!(
+            check(&path, true).unwrap_err().to_string(),
+            "compressed logs can't be followed"
+        );
+        assert_eq!(
+            check(&dir, false).unwrap_err().to_string(),
+            "is a directory"
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            check(&path, false).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -1,6 +1,19 @@
+pub mod ansi;
+pub mod build_script;
+pub mod ci;
+pub mod diagnostics;
+pub mod error;
+pub mod events;
+pub mod filter;
+pub mod generator;
+pub mod history;
+pub mod input;
 pub mod logone;
 pub mod parser;
+pub mod reports;
+pub mod rustc;
 pub mod sinks;
 
+pub use crate::error::LogoneError;
 pub use crate::logone::*;
 pub use crate::parser::*;
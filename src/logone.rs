@@ -1,4 +1,13 @@
-use anyhow::Result;
+use crate::ansi::has_sgr;
+use crate::ci::{self, CiFormat};
+use crate::diagnostics::{format_diagnostic, Diagnostics};
+use crate::error::{LogoneError, Result};
+use crate::events::{Event, EventHandler};
+use crate::filter::Filter;
+use crate::reports::Outcome;
+use crate::rustc::RustcDiagnostic;
+use crate::sinks::cargo_logs::{CargoSession, ExitStatus};
+use chrono::{DateTime, Duration, Utc};
 use clap::ValueEnum;
 use console::style;
 use crossterm::{
@@ -6,17 +15,65 @@ use crossterm::{
     terminal::{self, Clear, ClearType},
     ExecutableCommand,
 };
+use serde_json::Value;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::io::{stdout, Write};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
 pub enum LogLevel {
     Cargo,
     Errors,
     Verbose,
+    /// cargo for the `@cargo` sessions, errors for other derivations and nix errors
+    Hybrid,
+}
+
+impl LogLevel {
+    /// Whether `@cargo` messages are processed.
+    pub fn handles_cargo(&self) -> bool {
+        matches!(self, LogLevel::Cargo | LogLevel::Hybrid)
+    }
+}
+
+/// What to do with input lines which are not `@nix` messages, e.g. when
+/// stdout and stderr are merged with `2>&1`.
+#[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
+pub enum OtherLines {
+    /// print them verbatim above the status line
+    Forward,
+    /// print them in an "Other output" section at shutdown
+    Collect,
+    /// discard them, the default
+    Drop,
+}
+
+/// What logone writes to stdout.
+#[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// build logs, messages and the status line
+    Human,
+    /// one JSON object per `Event`, see `Event::to_json`
+    Ndjson,
+}
+
+/// Order in which `verbose` mode prints the build logs still buffered at shutdown.
+#[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
+pub enum FlushOrder {
+    /// successful, stopped, unfinished, failed; by start time within each group
+    Status,
+    /// by start time
+    Start,
+    /// by finish time, unfinished builds last
+    Finish,
+    /// by derivation name
+    Name,
+    /// by start time, failed builds last
+    FailuresLast,
+    /// print each log as soon as its build stops, the rest at shutdown by start time
+    Live,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum, Eq, PartialEq)]
@@ -38,8 +95,44 @@ pub struct NixMessage {
 
 pub type Id = u64;
 
+/// When a build activity started and stopped, as wall clock time and input line number.
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub started: DateTime<Utc>,
+    pub started_line: usize,
+    pub stopped: Option<DateTime<Utc>>,
+    pub stopped_line: Option<usize>,
+}
+
+impl Timing {
+    pub fn duration(&self) -> Option<Duration> {
+        self.stopped.map(|stopped| stopped - self.started)
+    }
+}
+
+/// A nix activity between its `start` and `stop`.
+#[derive(Debug, Clone)]
+pub struct Activity {
+    pub activity_type: Option<u64>,
+    pub text: String,
+    pub parent: Option<Id>,
+}
+
+/// An activity with its times, recorded for traces, see `LogOne::with_spans`.
+#[derive(Debug, Clone)]
+pub struct ActivitySpan {
+    pub activity: Activity,
+    /// `fields` of the start, e.g. [drv, machine, round, nrRounds] of a build
+    pub fields: Vec<Value>,
+    pub started: DateTime<Utc>,
+    pub stopped: Option<DateTime<Utc>>,
+    /// the phases (result type 104) and when they began
+    pub phases: Vec<(DateTime<Utc>, String)>,
+}
+
 pub struct LogOne {
     pub colored: bool,
+    keep_colors: bool,
     log_level: LogLevel,
     status_line_active: bool,
     targets: HashMap<String, u64>,
@@ -49,7 +142,41 @@ pub struct LogOne {
     pub nix_log_buffers_state: HashMap<Id, LogStatus>,
     pub cargo_log_buffers: HashMap<Id, Vec<String>>,
     pub cargo_log_buffers_state: HashMap<Id, LogStatus>,
+    pub cargo_sessions: HashMap<Id, CargoSession>,
+    /// every session which ended, a derivation can build several targets one after another
+    pub finished_sessions: Vec<(Id, CargoSession)>,
     pub drv_to_id: HashMap<String, u64>,
+    /// `/nix/store/...drv` of every build activity
+    pub id_to_drv: HashMap<Id, String>,
+    /// build activities nix reported as failed
+    pub build_failures: HashMap<Id, ExitStatus>,
+    retain_logs: bool,
+    /// every build log line, see `with_retained_logs`
+    pub build_logs: HashMap<Id, Vec<String>>,
+    /// bytes downloaded by each transfer (type 101) of a substitution
+    pub substituted_bytes: HashMap<Id, u64>,
+    record_spans: bool,
+    /// every activity, see `with_spans`
+    pub spans: HashMap<Id, ActivitySpan>,
+    pub diagnostics: Diagnostics,
+    other_lines: OtherLines,
+    pub other_output: Vec<String>,
+    strict: bool,
+    warnings: Vec<LogoneError>,
+    pub activities: HashMap<Id, Activity>,
+    pub timings: HashMap<Id, Timing>,
+    flush_order: FlushOrder,
+    event_handlers: Vec<EventHandler>,
+    output: OutputFormat,
+    // stopped build activities whose BuildFinished waits for nix's failure message
+    pending_builds: Vec<Id>,
+    local_crates: Vec<String>,
+    filter: Filter,
+    ci: Option<CiFormat>,
+    seen_diagnostics: HashSet<(String, String)>,
+    hidden_duplicates: usize,
+    hidden_dependency_warnings: usize,
+    line_number: usize,
     active: bool,
 }
 
@@ -63,6 +190,7 @@ impl LogOne {
     pub fn new(colored: bool, log_level: LogLevel) -> Self {
         Self {
             colored,
+            keep_colors: false,
             log_level,
             status_line_active: false,
             targets: HashMap::new(),
@@ -72,7 +200,34 @@ impl LogOne {
             nix_log_buffers_state: HashMap::new(),
             cargo_log_buffers: HashMap::new(),
             cargo_log_buffers_state: HashMap::new(),
+            cargo_sessions: HashMap::new(),
+            finished_sessions: Vec::new(),
             drv_to_id: HashMap::new(),
+            id_to_drv: HashMap::new(),
+            build_failures: HashMap::new(),
+            retain_logs: false,
+            build_logs: HashMap::new(),
+            substituted_bytes: HashMap::new(),
+            record_spans: false,
+            spans: HashMap::new(),
+            diagnostics: Diagnostics::default(),
+            other_lines: OtherLines::Drop,
+            other_output: Vec::new(),
+            strict: false,
+            warnings: Vec::new(),
+            activities: HashMap::new(),
+            timings: HashMap::new(),
+            flush_order: FlushOrder::Status,
+            event_handlers: Vec::new(),
+            output: OutputFormat::Human,
+            pending_builds: Vec::new(),
+            local_crates: Vec::new(),
+            filter: Filter::default(),
+            ci: None,
+            seen_diagnostics: HashSet::new(),
+            hidden_duplicates: 0,
+            hidden_dependency_warnings: 0,
+            line_number: 0,
             active: true,
         }
     }
@@ -80,31 +235,513 @@ impl LogOne {
     pub fn shutdown(&mut self) {
         if self.active {
             self.active = false;
+            self.report_unfinished();
+            self.finish_builds();
+            if self.level().handles_cargo() {
+                if let Some(msg) = self.hidden_warnings_message() {
+                    self.print_message(1, msg.as_str(), None);
+                }
+            }
             if self.level() == LogLevel::Verbose {
-                use LogStatus::*;
-                let desired_order = [
-                    FinishedWithSuccess,
-                    Stopped,
-                    Started,
-                    FinishedWithError
-                ];
-                for status in desired_order.iter() {
-                    let ids: Vec<Id> = self.nix_log_buffers_state
-                        .iter()
-                        .filter_map(|(&id, &state)| if state == *status { Some(id) } else { None })
-                        .collect();
-                    for id in ids {
-                        self.print_log_buffer_by_id(id);
-                    }
+                for id in self.flush_ids() {
+                    self.print_log_buffer_by_id(id);
+                }
+            }
+            if !self.other_output.is_empty() && self.human_output() {
+                self.clear_status();
+                println!("Other output:");
+                for line in std::mem::take(&mut self.other_output) {
+                    println!("  {}", line);
                 }
+                println!();
+                stdout().flush().unwrap();
+                self.draw_status();
+            }
+            self.diagnostics.finish();
+            if self.strict && self.diagnostics.count() > 0 {
+                self.clear_status();
+                eprintln!("logone: {}", self.diagnostics.summary());
+            }
+        }
+    }
+
+    /// Writes every diagnostic (see `Diagnostics`) and the final summary to `output`.
+    pub fn with_diagnostics(mut self, output: Box<dyn Write>) -> Self {
+        self.diagnostics.set_output(output);
+        self
+    }
+
+    /// Prints diagnostics to stderr as they occur.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Prints build output and messages which have colours of their own as they are
+    /// instead of colouring them.
+    pub fn with_keep_colors(mut self, keep_colors: bool) -> Self {
+        self.keep_colors = keep_colors;
+        self
+    }
+
+    pub fn keep_colors(&self) -> bool {
+        self.keep_colors && self.colored
+    }
+
+    pub fn with_flush_order(mut self, flush_order: FlushOrder) -> Self {
+        self.flush_order = flush_order;
+        self
+    }
+
+    pub fn flush_order(&self) -> FlushOrder {
+        self.flush_order
+    }
+
+    // Ids of the remaining nix log buffers in the configured FlushOrder
+    fn flush_ids(&self) -> Vec<Id> {
+        use LogStatus::*;
+        let mut ids: Vec<Id> = self
+            .nix_log_buffers_state
+            .keys()
+            .filter(|id| self.nix_log_buffers.contains_key(id))
+            .copied()
+            .collect();
+
+        let started = |id: &Id| (self.timings.get(id).map(|t| t.started_line), *id);
+        let failed = |id: &Id| self.nix_log_buffers_state.get(id) == Some(&FinishedWithError);
+        match self.flush_order {
+            FlushOrder::Status => {
+                let desired_order = [FinishedWithSuccess, Stopped, Started, FinishedWithError];
+                let group = |id: &Id| {
+                    let state = self.nix_log_buffers_state.get(id);
+                    desired_order.iter().position(|s| Some(s) == state)
+                };
+                ids.sort_by_key(|id| (group(id), started(id)));
+            }
+            FlushOrder::Start | FlushOrder::Live => ids.sort_by_key(started),
+            FlushOrder::Finish => ids.sort_by_key(|id| {
+                let stopped = self.timings.get(id).and_then(|t| t.stopped_line);
+                (stopped.is_none(), stopped, started(id))
+            }),
+            FlushOrder::Name => {
+                let names: HashMap<Id, String> = self
+                    .drv_to_id
+                    .iter()
+                    .map(|(drv, &id)| (id, derivation_name(drv)))
+                    .collect();
+                ids.sort_by(|a, b| names.get(a).cmp(&names.get(b)).then(a.cmp(b)));
+            }
+            FlushOrder::FailuresLast => ids.sort_by_key(|id| (failed(id), started(id))),
+        }
+        ids
+    }
+
+    /// Calls `handler` for every `Event`, in the order they happen.
+    pub fn on_event(&mut self, handler: impl FnMut(&Event) + 'static) {
+        self.event_handlers.push(Box::new(handler));
+    }
+
+    pub fn with_event_handler(mut self, handler: impl FnMut(&Event) + 'static) -> Self {
+        self.on_event(handler);
+        self
+    }
+
+    pub(crate) fn has_event_handlers(&self) -> bool {
+        !self.event_handlers.is_empty()
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        for handler in self.event_handlers.iter_mut() {
+            handler(&event);
+        }
+    }
+
+    /// With `OutputFormat::Ndjson` every `Event` is written to stdout as a line of JSON
+    /// instead of the human readable output, which is dropped.
+    pub fn with_output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        if output == OutputFormat::Ndjson {
+            self.on_event(|event| {
+                let mut stdout = stdout().lock();
+                let _ = writeln!(stdout, "{}", event.to_json());
+                let _ = stdout.flush();
+            });
+        }
+        self
+    }
+
+    pub fn output(&self) -> OutputFormat {
+        self.output
+    }
+
+    fn human_output(&self) -> bool {
+        self.output == OutputFormat::Human
+    }
+
+    // nix reports a failed build after the stop of its activity, so BuildFinished
+    // is sent when the next activity starts or at shutdown
+    pub(crate) fn build_stopped(&mut self, id: Id) {
+        if self.has_event_handlers() && self.id_to_drv.contains_key(&id) {
+            self.pending_builds.push(id);
+        }
+    }
+
+    pub(crate) fn flush_stopped_builds(&mut self) {
+        for id in std::mem::take(&mut self.pending_builds) {
+            let outcome = if self.build_failures.contains_key(&id) {
+                Outcome::Failure
+            } else {
+                Outcome::Success
+            };
+            self.emit_build_finished(id, outcome);
+        }
+    }
+
+    // BuildFinished for the stopped builds and, as unfinished, the running ones
+    fn finish_builds(&mut self) {
+        if !self.has_event_handlers() {
+            return;
+        }
+        self.flush_stopped_builds();
+        let mut running: Vec<(usize, Id)> = self
+            .timings
+            .iter()
+            .filter(|(id, timing)| timing.stopped.is_none() && self.id_to_drv.contains_key(id))
+            .map(|(&id, timing)| (timing.started_line, id))
+            .collect();
+        running.sort();
+        for (_, id) in running {
+            self.emit_build_finished(id, Outcome::Unfinished);
+        }
+    }
+
+    fn emit_build_finished(&mut self, id: Id, outcome: Outcome) {
+        let drv = self.id_to_drv.get(&id).cloned().unwrap_or_default();
+        let event = Event::BuildFinished {
+            id,
+            drv,
+            outcome,
+            exit_status: self.build_failures.get(&id).copied(),
+            duration: self.timings.get(&id).and_then(|timing| timing.duration()),
+        };
+        self.emit(event);
+    }
+
+    /// Names of the crates of the workspace. When set, warnings of all other crates are
+    /// hidden in cargo mode, like cargo does with `--cap-lints` for dependencies.
+    pub fn with_local_crates<S: Into<String>>(
+        mut self,
+        crates: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.local_crates = crates
+            .into_iter()
+            .map(|name| name.into().replace('-', "_"))
+            .collect();
+        self
+    }
+
+    pub fn is_local_crate(&self, crate_name: &str) -> bool {
+        self.local_crates.is_empty() || self.local_crates.contains(&crate_name.replace('-', "_"))
+    }
+
+    /// Decides whether a rustc diagnostic of `crate_name` is printed: errors always are,
+    /// warnings of dependencies (see `with_local_crates`) are not and the other
+    /// diagnostics only the first time they are seen for the crate, as the (lib), (bin)
+    /// and tests builds of a crate report the same warnings.
+    pub(crate) fn show_diagnostic(
+        &mut self,
+        crate_name: &str,
+        diagnostic: &RustcDiagnostic,
+    ) -> bool {
+        if diagnostic.level.starts_with("error") {
+            return true;
+        }
+        if !self.is_local_crate(crate_name) {
+            if !diagnostic.is_summary() {
+                self.hidden_dependency_warnings += 1;
+            }
+            return false;
+        }
+        if diagnostic.is_summary() {
+            return true;
+        }
+        let key = (crate_name.to_string(), diagnostic.dedup_key());
+        if self.seen_diagnostics.insert(key) {
+            true
+        } else {
+            self.hidden_duplicates += 1;
+            false
+        }
+    }
+
+    /// The rustc diagnostics of all finished sessions with their session, without
+    /// rustc's summaries and each only once per crate, like `show_diagnostic` prints
+    /// them. Warnings of dependencies are included.
+    pub fn unique_diagnostics(&self) -> Vec<(&CargoSession, &RustcDiagnostic)> {
+        let mut seen: HashSet<(&str, String)> = HashSet::new();
+        self.finished_sessions
+            .iter()
+            .flat_map(|(_, session)| {
+                session
+                    .diagnostics
+                    .iter()
+                    .map(move |diagnostic| (session, diagnostic))
+            })
+            .filter(|(session, diagnostic)| {
+                !diagnostic.is_summary()
+                    && seen.insert((session.crate_name.as_str(), diagnostic.dedup_key()))
+            })
+            .collect()
+    }
+
+    /// Number of rustc diagnostics not printed because they were printed before.
+    pub fn hidden_duplicates(&self) -> usize {
+        self.hidden_duplicates
+    }
+
+    /// Number of rustc diagnostics not printed because they belong to a dependency.
+    pub fn hidden_dependency_warnings(&self) -> usize {
+        self.hidden_dependency_warnings
+    }
+
+    // "warning: 2 duplicate warnings, 1 warning of dependencies hidden"
+    fn hidden_warnings_message(&self) -> Option<String> {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        let mut hidden = Vec::new();
+        if self.hidden_duplicates > 0 {
+            hidden.push(format!(
+                "{} duplicate warning{}",
+                self.hidden_duplicates,
+                plural(self.hidden_duplicates)
+            ));
+        }
+        if self.hidden_dependency_warnings > 0 {
+            hidden.push(format!(
+                "{} warning{} of dependencies",
+                self.hidden_dependency_warnings,
+                plural(self.hidden_dependency_warnings)
+            ));
+        }
+        if hidden.is_empty() {
+            None
+        } else {
+            Some(format!("warning: {} hidden", hidden.join(", ")))
+        }
+    }
+
+    /// Keeps the complete log of every build in `build_logs`, independent of the log
+    /// level, for reports written at the end.
+    pub fn with_retained_logs(mut self, retain_logs: bool) -> Self {
+        self.retain_logs = retain_logs;
+        self
+    }
+
+    pub fn retains_logs(&self) -> bool {
+        self.retain_logs
+    }
+
+    /// Keeps every activity with its start and stop time in `spans`, for traces.
+    pub fn with_spans(mut self, record_spans: bool) -> Self {
+        self.record_spans = record_spans;
+        self
+    }
+
+    pub fn records_spans(&self) -> bool {
+        self.record_spans
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Adds the markup of a CI system: annotations for diagnostics and failed
+    /// builds, collapsible groups around build logs. There is no status line then.
+    pub fn with_ci(mut self, ci: Option<CiFormat>) -> Self {
+        self.ci = ci;
+        self
+    }
+
+    pub fn ci(&self) -> Option<CiFormat> {
+        self.ci
+    }
+
+    /// Records that nix reported the builder of the build `id` as failed. The first
+    /// report is annotated as an error on GitHub Actions.
+    pub fn record_build_failure(&mut self, id: Id, exit_status: ExitStatus) {
+        let first = self.build_failures.insert(id, exit_status).is_none();
+        if !first || self.ci != Some(CiFormat::Github) {
+            return;
+        }
+        if let Some(drv) = self.id_to_drv.get(&id) {
+            let message = format!("builder for '{}' failed with {}", drv, exit_status);
+            let annotation = ci::github_message("error", &derivation_name(drv), &message);
+            self.print_line(&annotation);
+        }
+    }
+
+    pub fn with_other_lines(mut self, other_lines: OtherLines) -> Self {
+        self.other_lines = other_lines;
+        self
+    }
+
+    /// Handles an input line which is not an `@nix` message according to `OtherLines`.
+    /// Lines which are not dropped are sent as `Event::OtherLine` too.
+    pub fn other_line(&mut self, line: &str) {
+        if self.other_lines != OtherLines::Drop {
+            self.emit(Event::OtherLine {
+                line: line.to_string(),
+            });
+        }
+        match self.other_lines {
+            OtherLines::Forward => self.print_line(line),
+            OtherLines::Collect => self.other_output.push(line.to_string()),
+            OtherLines::Drop => {}
+        }
+    }
+
+    /// Prints `line` above the status line as it is, without colours.
+    pub fn print_line(&mut self, line: &str) {
+        if !self.human_output() {
+            return;
+        }
+        self.clear_status();
+        println!("{}", line);
+        stdout().flush().unwrap();
+        self.draw_status();
+    }
+
+    pub fn diagnose(&mut self, error: &LogoneError) {
+        self.diagnostics.record(error);
+        if self.strict {
+            self.clear_status();
+            eprintln!("logone: {}", format_diagnostic(error));
+            self.draw_status();
+        }
+    }
+
+    /// Queues a problem which does not stop the current line from being processed.
+    pub(crate) fn warn(&mut self, error: LogoneError) {
+        self.warnings.push(error);
+    }
+
+    pub(crate) fn take_warnings(&mut self) -> Vec<LogoneError> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Keeps track of started activities to detect duplicate starts and
+    // results/stops for ids which were never started.
+    pub(crate) fn track_activity(&mut self, action: &str, id: Id, activity: Activity) {
+        let error = match action {
+            "start" => match self.activities.insert(id, activity) {
+                Some(_) => LogoneError::DuplicateStart(id),
+                None => return,
+            },
+            "result" if !self.activities.contains_key(&id) => LogoneError::UnknownActivity {
+                id,
+                action: "result",
+            },
+            "stop" if self.activities.remove(&id).is_none() => {
+                LogoneError::UnknownActivity { id, action: "stop" }
+            }
+            _ => return,
+        };
+        self.warn(error);
+    }
+
+    /// Lists activities and @cargo sessions which were started but never finished, e.g.
+    /// because nix was killed, and removes them from the status line.
+    fn report_unfinished(&mut self) {
+        let mut activities: Vec<(Id, String)> = self
+            .activities
+            .iter()
+            .filter(|(_, activity)| !activity.text.is_empty())
+            .map(|(&id, activity)| (id, activity.text.clone()))
+            .collect();
+        activities.sort();
+
+        let mut sessions: Vec<(Id, String)> = self
+            .cargo_log_buffers_state
+            .iter()
+            .filter(|(_, &state)| state == LogStatus::Started || state == LogStatus::Stopped)
+            .filter_map(|(&id, _)| {
+                self.cargo_sessions
+                    .get(&id)
+                    .map(|session| (id, session.target_name()))
+            })
+            .collect();
+        sessions.sort();
+
+        if activities.is_empty() && sessions.is_empty() {
+            return;
+        }
+
+        for (id, target_name) in &sessions {
+            if self.cargo_log_buffers_state.get(id) == Some(&LogStatus::Started) {
+                self.cargo_log_buffers_state.insert(*id, LogStatus::Stopped);
+                let _ = self.target_remove(target_name.clone());
             }
         }
+
+        if !self.human_output() {
+            return;
+        }
+        self.clear_status();
+        let header = "Unfinished at shutdown:";
+        if self.colored {
+            println!("{}", style(header).yellow());
+        } else {
+            println!("{}", header);
+        }
+        for (_, text) in &activities {
+            println!("  {}", text);
+        }
+        for (_, target_name) in &sessions {
+            println!("  {} (@cargo session)", target_name);
+        }
+        println!();
+        stdout().flush().unwrap();
+
+        if matches!(self.level(), LogLevel::Errors | LogLevel::Hybrid) {
+            for (id, _) in &activities {
+                self.print_log_buffer_by_id(*id);
+            }
+        }
+        self.draw_status();
+    }
+
+    /// Bytes downloaded from binary caches so far.
+    pub fn total_substituted_bytes(&self) -> u64 {
+        self.substituted_bytes.values().sum()
+    }
+
+    /// The last `[done, expected, running, failed]` nix reported for its builds.
+    pub fn stats(&self) -> Option<(u64, u64, u64, u64)> {
+        self.last_stats
     }
 
     pub fn level(&self) -> LogLevel {
         self.log_level
     }
 
+    /// Number of input lines passed to `parse_nix_line` so far.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    pub(crate) fn next_line(&mut self) -> usize {
+        self.line_number += 1;
+        self.line_number
+    }
+
     // Snapshot of targets with their counts
     fn snapshot_targets(&self) -> Vec<(String, u64)> {
         let mut snapshot: Vec<(String, u64)> = self
@@ -148,6 +785,10 @@ impl LogOne {
 
     // Draw status line with current stored values (ignores anti-flicker logic)
     pub fn draw_status(&mut self) {
+        // CI logs are no terminals, the cursor movement would end up in them
+        if self.ci.is_some() || !self.human_output() {
+            return;
+        }
         // Only redraw if we have stats to show
         if let Some((done, expected, running, failed)) = self.last_stats {
             let base_status = format!(
@@ -186,6 +827,10 @@ impl LogOne {
 
     pub fn update_stats(&mut self, done: u64, expected: u64, running: u64, failed: u64) {
         let current_stats = (done, expected, running, failed);
+        if self.ci.is_some() || !self.human_output() {
+            self.last_stats = Some(current_stats);
+            return;
+        }
         // Get snapshot for consistent hash and display
         let targets_snapshot = self.snapshot_targets();
         let current_targets_hash = self.calculate_targets_hash(&targets_snapshot);
@@ -260,28 +905,28 @@ impl LogOne {
         // Extract the buffer
         let buffer = self.nix_log_buffers.remove(&id);
 
-        if let Some(buffer) = buffer {
+        if let Some(buffer) = buffer.filter(|_| self.human_output()) {
             // Clear status line if active
             self.clear_status();
 
-            println!("Build log for '{}':", drv);
+            let header = format!("Build log for '{}':", drv);
+            let group = format!("{}-{}", derivation_name(&drv), id);
+            match self.ci {
+                Some(ci) => println!("{}", ci::group_start(ci, &group, &header)),
+                None => println!("{}", header),
+            }
             for message in buffer {
                 match message.message_type {
-                    Some(101) => {
-                        // resBuildLogLine
-                        if self.colored {
-                            println!("  {}", style(&message.content).dim());
-                        } else {
-                            println!("  {}", message.content);
-                        }
+                    // resBuildLogLine, keeping its own colours
+                    Some(101) if self.keep_colors() && has_sgr(&message.content) => {
+                        println!("  {}", message.content);
+                    }
+                    Some(101) if self.colored => {
+                        println!("  {}", style(&message.content).dim());
                     }
-                    Some(104) => {
-                        // resSetPhase
-                        if self.colored {
-                            println!("  {}", style(&message.content).cyan());
-                        } else {
-                            println!("  {}", message.content);
-                        }
+                    // resSetPhase
+                    Some(104) if self.colored => {
+                        println!("  {}", style(&message.content).cyan());
                     }
                     _ => {
                         println!("  {}", message.content);
@@ -289,6 +934,9 @@ impl LogOne {
                 }
             }
             println!(); // Empty line after log
+            if let Some(ci) = self.ci {
+                println!("{}", ci::group_end(ci, &group));
+            }
             stdout().flush().unwrap();
 
             // Redraw status line after printing log buffer
@@ -297,6 +945,10 @@ impl LogOne {
     }
 
     pub fn print_message(&mut self, level: u64, msg: &str, file: Option<&str>) {
+        if self.filter.hides_message(msg) || !self.human_output() {
+            return;
+        }
+
         // Clear status line if active
         if self.status_line_active {
             let mut stdout = stdout();
@@ -309,10 +961,12 @@ impl LogOne {
         let formatted_msg = if let Some(file_path) = file {
             format!("{}: {}", file_path, msg)
         } else {
-            format!("{}", msg)
+            msg.to_string()
         };
 
-        if self.colored {
+        if self.keep_colors() && has_sgr(&formatted_msg) {
+            println!("{}", formatted_msg);
+        } else if self.colored {
             let styled_msg = match level {
                 0 => style(formatted_msg).red(),
                 1 => style(formatted_msg).yellow(),
@@ -405,3 +1059,135 @@ impl LogOne {
         hasher.finish()
     }
 }
+
+/// `serde-1_0_218-472e28b9f131b02c` for `building '/nix/store/<hash>-serde-1_0_218-472e28b9f131b02c.drv'`
+pub fn derivation_name(text: &str) -> String {
+    let path = match text.find("/nix/store/") {
+        Some(start) => &text[start + "/nix/store/".len()..],
+        None => return text.to_string(),
+    };
+    let path = path.split(['\'', ' ']).next().unwrap_or(path);
+    let name = path.split_once('-').map(|(_, name)| name).unwrap_or(path);
+    name.trim_end_matches(".drv").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::rustc_diagnostic;
+    use crate::parser::parse_nix_line;
+
+    fn collected(mut logone: LogOne) -> Vec<String> {
+        for line in [
+            "$ nix build .#hello",
+            r#"@nix {"action":"msg","level":4,"msg":"hello"}"#,
+            "/nix/store/3xx-hello-2.12.1",
+        ] {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        std::mem::take(&mut logone.other_output)
+    }
+
+    fn diagnostic(level: &str, message: &str, line: u64) -> RustcDiagnostic {
+        let value = rustc_diagnostic(level, Some("unused"), message, "src/lib.rs", line, 5);
+        RustcDiagnostic::from_value(&value).unwrap()
+    }
+
+    #[test]
+    fn duplicate_diagnostics_are_hidden() {
+        let mut logone = LogOne::new(false, LogLevel::Cargo);
+        let warning = diagnostic("warning", "unused import", 3);
+        assert!(logone.show_diagnostic("memchr", &warning));
+        // the same warning of the (test) build of the crate
+        assert!(!logone.show_diagnostic("memchr", &warning));
+        // another crate, another line
+        assert!(logone.show_diagnostic("libc", &warning));
+        assert!(logone.show_diagnostic("memchr", &diagnostic("warning", "unused import", 4)));
+        // errors are always shown
+        let error = diagnostic("error", "mismatched types", 9);
+        assert!(logone.show_diagnostic("memchr", &error));
+        assert!(logone.show_diagnostic("memchr", &error));
+        assert_eq!(logone.hidden_duplicates(), 1);
+        assert_eq!(logone.hidden_dependency_warnings(), 0);
+        assert_eq!(
+            logone.hidden_warnings_message().as_deref(),
+            Some("warning: 1 duplicate warning hidden")
+        );
+    }
+
+    #[test]
+    fn dependency_warnings_are_hidden() {
+        let mut logone = LogOne::new(false, LogLevel::Cargo).with_local_crates(["my-crate"]);
+        let warning = diagnostic("warning", "unused import", 3);
+        let summary = RustcDiagnostic {
+            level: "warning".to_string(),
+            message: "1 warning emitted".to_string(),
+            ..Default::default()
+        };
+        assert!(logone.is_local_crate("my_crate"));
+        assert!(logone.show_diagnostic("my_crate", &warning));
+        assert!(logone.show_diagnostic("my_crate", &summary));
+        assert!(!logone.show_diagnostic("libc", &warning));
+        assert!(!logone.show_diagnostic("libc", &warning));
+        assert!(!logone.show_diagnostic("libc", &summary));
+        assert!(logone.show_diagnostic("libc", &diagnostic("error", "mismatched types", 9)));
+        assert_eq!(logone.hidden_dependency_warnings(), 2);
+        assert_eq!(logone.hidden_duplicates(), 0);
+        assert_eq!(
+            logone.hidden_warnings_message().as_deref(),
+            Some("warning: 2 warnings of dependencies hidden")
+        );
+    }
+
+    #[test]
+    fn other_lines() {
+        let logone = || LogOne::new(false, LogLevel::Errors);
+        assert!(collected(logone()).is_empty());
+        assert_eq!(
+            collected(logone().with_other_lines(OtherLines::Collect)),
+            vec!["$ nix build .#hello", "/nix/store/3xx-hello-2.12.1"]
+        );
+        assert!(collected(logone().with_other_lines(OtherLines::Forward)).is_empty());
+    }
+
+    #[test]
+    fn flush_ids() {
+        use LogStatus::*;
+        let mut logone = LogOne::new(false, LogLevel::Verbose);
+        let builds = [
+            (10, "zlib-1.3.1", FinishedWithSuccess, 1, Some(7)),
+            (11, "bash-5.2p37", FinishedWithError, 2, Some(3)),
+            (12, "curl-8.12.1", Started, 4, None),
+            (13, "acl-2.3.2", Stopped, 5, Some(6)),
+        ];
+        for (id, name, state, started_line, stopped_line) in builds {
+            let now = Utc::now();
+            logone.nix_log_buffers.insert(id, Vec::new());
+            logone.nix_log_buffers_state.insert(id, state);
+            logone
+                .drv_to_id
+                .insert(format!("building '/nix/store/0aa-{}.drv'", name), id);
+            logone.timings.insert(
+                id,
+                Timing {
+                    started: now,
+                    started_line,
+                    stopped: stopped_line.map(|_| now),
+                    stopped_line,
+                },
+            );
+        }
+
+        for (flush_order, expected) in [
+            (FlushOrder::Status, vec![10, 13, 12, 11]),
+            (FlushOrder::Start, vec![10, 11, 12, 13]),
+            (FlushOrder::Finish, vec![11, 13, 10, 12]),
+            (FlushOrder::Name, vec![13, 11, 12, 10]),
+            (FlushOrder::FailuresLast, vec![10, 12, 13, 11]),
+            (FlushOrder::Live, vec![10, 11, 12, 13]),
+        ] {
+            logone.flush_order = flush_order;
+            assert_eq!(logone.flush_ids(), expected, "{:?}", flush_order);
+        }
+    }
+}
@@ -1,14 +1,33 @@
-use clap::Parser;
-use logone::LogLevel;
-use std::io::{stdin, BufRead, BufReader};
-
-mod logone;
-mod parser;
-mod sinks;
+use clap::{Parser, Subcommand};
+use logone::{
+    ci::CiFormat, filter::Filter, generator, history, input, parser, reports, sinks::cargo_logs,
+    FlushOrder, LogLevel, OtherLines, OutputFormat,
+};
+use regex::Regex;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufWriter};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Recorded logs to read one after another, also gzip, zstd or xz compressed,
+    /// `-` for stdin (default)
+    #[arg(value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Keep reading the last FILE as it grows, like `tail -f`, until Ctrl-C
+    #[arg(short, long)]
+    follow: bool,
+
     /// Enable JSON parsing mode
     #[arg(short, long)]
     json: bool,
@@ -20,29 +39,392 @@ struct Args {
     /// Set log level for filtering messages
     #[arg(short, long, value_enum, default_value_t = LogLevel::Cargo)]
     level: LogLevel,
+
+    /// Order of the build logs printed at the end in verbose mode
+    #[arg(long, value_enum, default_value_t = FlushOrder::Status)]
+    order: FlushOrder,
+
+    /// Keep the colours of build output instead of re-colouring it
+    #[arg(long)]
+    keep_colors: bool,
+
+    /// Write human readable output or one versioned JSON event per line to stdout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    output: OutputFormat,
+
+    /// What to do with input lines which are not @nix messages
+    #[arg(long, value_enum, default_value_t = OtherLines::Drop)]
+    other_lines: OtherLines,
+
+    /// Write malformed, unknown or out-of-protocol input lines to FILE
+    #[arg(long, value_name = "FILE")]
+    diagnostics: Option<PathBuf>,
+
+    /// Report diagnostics on stderr and exit with 1 if there were any
+    #[arg(long)]
+    strict: bool,
+
+    /// Crate of the workspace, warnings of all other crates are hidden (repeatable)
+    #[arg(long = "local-crate", value_name = "NAME")]
+    local_crates: Vec<String>,
+
+    /// Only show derivations whose name matches REGEX (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    include_drv: Vec<Regex>,
+
+    /// Drop the logs and @cargo sessions of derivations whose name matches REGEX (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    exclude_drv: Vec<Regex>,
+
+    /// Drop messages and build log lines matching REGEX (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    hide_message: Vec<Regex>,
+
+    /// Print nix messages of verbosity LEVEL (0 error ... 7 vomit) in every log level (repeatable)
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u64).range(0..=7))]
+    show_level: Vec<u64>,
+
+    /// Never print nix messages of verbosity LEVEL (repeatable)
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u64).range(0..=7))]
+    hide_level: Vec<u64>,
+
+    /// Print the build log of derivations whose name matches REGEX even when they succeed (repeatable)
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    always_show: Vec<Regex>,
+
+    /// Add annotations and collapsible log groups for a CI system, detected from the
+    /// environment if not given
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    ci: Option<CiFormat>,
+
+    /// Don't add CI markup, even when running in a CI
+    #[arg(long, conflicts_with = "ci")]
+    no_ci: bool,
+
+    /// Write the files each crate target produced to FILE, like cargo's compiler-artifact messages
+    #[arg(long, value_name = "FILE")]
+    artifacts_json: Option<PathBuf>,
+
+    /// Write a JUnit XML report with a test case per derivation and crate to FILE
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+
+    /// Write the rustc diagnostics of all crates as SARIF to FILE, needs --level cargo or hybrid
+    #[arg(long, value_name = "FILE")]
+    sarif: Option<PathBuf>,
+
+    /// Write a self-contained HTML report with statistics, timeline and build logs to FILE
+    #[arg(long, value_name = "FILE")]
+    html: Option<PathBuf>,
+
+    /// Write a Markdown summary to FILE, it is also appended to $GITHUB_STEP_SUMMARY if that is set
+    #[arg(long, value_name = "FILE")]
+    summary_md: Option<PathBuf>,
+
+    /// Write Prometheus metrics for the textfile collector to FILE
+    #[arg(long, value_name = "FILE")]
+    metrics_file: Option<PathBuf>,
+
+    /// Also write the metrics every SECONDS while reading the input
+    #[arg(long, value_name = "SECONDS", requires = "metrics_file")]
+    metrics_interval: Option<u64>,
+
+    /// Send the activities as OTLP/HTTP spans to URL, e.g. http://localhost:4318
+    #[arg(long, value_name = "URL", value_parser = reports::otlp::Endpoint::parse)]
+    otlp_endpoint: Option<reports::otlp::Endpoint>,
+
+    /// Append the activities as a line of OTLP/JSON spans to FILE
+    #[arg(long, value_name = "FILE")]
+    otlp_file: Option<PathBuf>,
+
+    /// Append the duration, outcome and warnings of each derivation to the history FILE,
+    /// only for builds read from stdin
+    #[arg(long, value_name = "FILE")]
+    history: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a synthetic internal-json stream for the given scenario
+    Gen {
+        #[arg(value_enum)]
+        scenario: generator::Scenario,
+
+        /// Seed for store hashes and the interleaving of parallel builds
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Analyse the runs recorded with --history
+    History {
+        /// History file written by --history
+        #[arg(long, value_name = "FILE")]
+        file: PathBuf,
+
+        #[command(subcommand)]
+        query: HistoryQuery,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryQuery {
+    /// Duration and outcome of the derivations whose name matches REGEX
+    Trend {
+        #[arg(value_name = "REGEX", value_parser = Regex::new)]
+        pattern: Regex,
+
+        /// Number of builds shown per derivation
+        #[arg(long, default_value_t = 20)]
+        runs: usize,
+    },
+    /// Derivations which alternate between success and failure
+    Flaky {
+        /// Number of runs looked at
+        #[arg(long, default_value_t = 20)]
+        runs: usize,
+    },
+    /// Changes of the last run against the one before
+    Compare {
+        /// Minimal change of a duration in percent
+        #[arg(long, default_value_t = 20.0)]
+        threshold: f64,
+
+        /// Ignore durations below SECONDS, they vary too much
+        #[arg(long, value_name = "SECONDS", default_value_t = 1.0)]
+        min_duration: f64,
+    },
+}
+
+/// Sends the lines of all inputs one after another, following the last one with
+/// `--follow`. Stops at the first error, after sending it.
+fn read_inputs(
+    inputs: &[PathBuf],
+    follow: bool,
+    stop: Arc<AtomicBool>,
+    lines: SyncSender<io::Result<String>>,
+) {
+    for (index, path) in inputs.iter().enumerate() {
+        let reader = if follow && index + 1 == inputs.len() {
+            input::follow(path, stop.clone())
+        } else {
+            input::open(path)
+        };
+        let reader = match reader {
+            Ok(reader) => reader,
+            Err(e) => {
+                let e = io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+                let _ = lines.send(Err(e));
+                return;
+            }
+        };
+        for line in reader.lines() {
+            let failed = line.is_err();
+            if lines.send(line).is_err() || failed {
+                return;
+            }
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
+    match args.command {
+        Some(Command::Gen { scenario, seed }) => {
+            for line in generator::generate(scenario, seed) {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        Some(Command::History { file, query }) => {
+            let runs = history::load(&file)?;
+            let mut out = std::io::stdout().lock();
+            match query {
+                HistoryQuery::Trend {
+                    pattern,
+                    runs: limit,
+                } => history::write_trend(&runs, &pattern, limit, &mut out)?,
+                HistoryQuery::Flaky { runs: limit } => {
+                    history::write_flaky(&runs, limit, &mut out)?
+                }
+                HistoryQuery::Compare {
+                    threshold,
+                    min_duration,
+                } => history::write_compare(&runs, threshold, min_duration, &mut out)?,
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
     if !args.json {
         eprintln!("JSON mode is required. Use --json flag.");
         std::process::exit(1);
     }
+    // the sessions are only collected when the @cargo messages are processed
+    if args.sarif.is_some() && !args.level.handles_cargo() {
+        eprintln!("--sarif requires --level cargo or --level hybrid.");
+        std::process::exit(1);
+    }
+    // the history is about builds as they happen, replaying a log would add a run
+    // with the time and durations of the replay
+    if args.history.is_some() && args.files.iter().any(|path| path.as_os_str() != "-") {
+        eprintln!("--history can only record builds read from stdin, not recorded logs.");
+        std::process::exit(1);
+    }
+
+    let mut filter = Filter::default();
+    for pattern in args.include_drv {
+        filter = filter.include_derivation(pattern);
+    }
+    for pattern in args.exclude_drv {
+        filter = filter.exclude_derivation(pattern);
+    }
+    for pattern in args.hide_message {
+        filter = filter.hide_message(pattern);
+    }
+    for level in args.show_level {
+        filter = filter.show_level(level);
+    }
+    for level in args.hide_level {
+        filter = filter.hide_level(level);
+    }
+    for pattern in args.always_show {
+        filter = filter.always_show(pattern);
+    }
+
+    // GitHub shows the step summary on the page of the workflow run
+    let step_summary = std::env::var_os("GITHUB_STEP_SUMMARY")
+        .filter(|_| !args.no_ci)
+        .map(PathBuf::from);
 
     // Initialize display manager
-    let mut logone = logone::LogOne::new(!args.no_color, args.level);
+    let mut logone = logone::LogOne::new(!args.no_color, args.level)
+        .with_strict(args.strict)
+        .with_keep_colors(args.keep_colors)
+        .with_flush_order(args.order)
+        .with_other_lines(args.other_lines)
+        .with_output(args.output)
+        .with_local_crates(args.local_crates)
+        .with_filter(filter)
+        .with_ci(if args.no_ci {
+            None
+        } else {
+            args.ci.or_else(CiFormat::detect)
+        })
+        .with_retained_logs(
+            args.junit.is_some()
+                || args.html.is_some()
+                || args.summary_md.is_some()
+                || step_summary.is_some(),
+        )
+        .with_spans(args.otlp_endpoint.is_some() || args.otlp_file.is_some());
+    if let Some(path) = &args.diagnostics {
+        logone = logone.with_diagnostics(Box::new(BufWriter::new(File::create(path)?)));
+    }
 
-    // Read from stdin line by line
-    let stdin = stdin();
-    let reader = BufReader::new(stdin);
+    let inputs = if args.files.is_empty() {
+        vec![PathBuf::from("-")]
+    } else {
+        args.files.clone()
+    };
+    // all inputs are checked before the first line is processed
+    for (index, path) in inputs.iter().enumerate() {
+        if let Err(e) = input::check(path, args.follow && index + 1 == inputs.len()) {
+            eprintln!("logone: {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    // Ctrl-C ends following, so the reports are still written
+    let stop = Arc::new(AtomicBool::new(false));
+    if args.follow {
+        let stop = stop.clone();
+        ctrlc::set_handler(move || stop.store(true, Ordering::Relaxed))?;
+    }
+
+    let metrics_interval = args.metrics_interval.map(Duration::from_secs);
+    let mut metrics_written = Instant::now();
+    // the input is read on its own thread, so the metrics are also written while
+    // the build is quiet
+    let (sender, lines) = mpsc::sync_channel(1024);
+    let follow = args.follow;
+    thread::spawn(move || read_inputs(&inputs, follow, stop, sender));
+    let mut read_error = None;
+    loop {
+        let line = match metrics_interval {
+            Some(interval) => {
+                lines.recv_timeout(interval.saturating_sub(metrics_written.elapsed()))
+            }
+            None => lines.recv().map_err(RecvTimeoutError::from),
+        };
+        match line {
+            // Parse errors are recorded in logone.diagnostics
+            Ok(Ok(line)) => {
+                let _ = parser::parse_nix_line(&line, &mut logone);
+            }
+            // the reports are still written for what was read
+            Ok(Err(e)) => {
+                read_error = Some(e);
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if let (Some(path), Some(interval)) = (&args.metrics_file, metrics_interval) {
+            if metrics_written.elapsed() >= interval {
+                reports::prometheus::write_metrics_file(&logone, path)?;
+                metrics_written = Instant::now();
+            }
+        }
+    }
 
-    for line in reader.lines() {
-        let line = line?;
-        if let Err(_) = parser::parse_nix_line(&line, &mut logone) {
-            // Silently ignore parse errors
+    logone.shutdown();
+    if let Some(path) = &args.artifacts_json {
+        let artifacts = cargo_logs::compiler_artifacts(&logone);
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &artifacts)?;
+    }
+    if let Some(path) = &args.junit {
+        reports::junit::write_junit(&logone, &mut BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = &args.sarif {
+        reports::sarif::write_sarif(&logone, &mut BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = &args.html {
+        reports::html::write_html(&logone, &mut BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(path) = &args.summary_md {
+        reports::summary::write_summary(&logone, &mut BufWriter::new(File::create(path)?))?;
+    }
+    // the other steps of the job write to the same file
+    if let Some(path) = &step_summary {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        reports::summary::write_summary(&logone, &mut BufWriter::new(file))?;
+    }
+    if let Some(path) = &args.metrics_file {
+        reports::prometheus::write_metrics_file(&logone, path)?;
+    }
+    if args.otlp_endpoint.is_some() || args.otlp_file.is_some() {
+        let context = reports::otlp::TraceContext::from_env();
+        if let Some(path) = &args.otlp_file {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            reports::otlp::write_otlp(&logone, &context, &mut BufWriter::new(file))?;
+        }
+        if let Some(endpoint) = &args.otlp_endpoint {
+            // the build is done, a missing collector should not fail it
+            if let Err(e) = reports::otlp::export_http(&logone, &context, endpoint) {
+                eprintln!("logone: OTLP export to {} failed: {}", endpoint, e);
+            }
         }
     }
+    if let Some(path) = &args.history {
+        history::append(path, &history::Run::from_logone(&logone))?;
+    }
+    if let Some(e) = read_error {
+        eprintln!("logone: reading the input failed: {}", e);
+        std::process::exit(1);
+    }
+    if args.strict && logone.diagnostics.count() > 0 {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -1,23 +1,25 @@
 use crate::{
+    ansi::{sanitize_ansi, strip_ansi},
+    error::{LogoneError, Result},
+    events::Event,
     logone,
-    sinks::{cargo_logs, nix_build_statistics, nix_logs},
-    LogLevel,
+    logone::Id,
+    sinks::{
+        cargo_logs::{self, ExitStatus},
+        nix_build_statistics, nix_logs,
+    },
+    FlushOrder, LogLevel,
 };
-use anyhow::{anyhow, Result};
-use regex::Regex;
+use chrono::Utc;
 use serde_json::{Map, Value};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Mutex, OnceLock};
 
-static ANSI_ESCAPE_RE: OnceLock<Regex> = OnceLock::new();
 static DERIVATION_FAILURES: OnceLock<Mutex<HashMap<u64, bool>>> = OnceLock::new();
 static ACTIVE_DERIVATIONS: OnceLock<Mutex<HashSet<u64>>> = OnceLock::new();
 static DERIVATION_NAMES: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
 
-fn get_ansi_regex() -> &'static Regex {
-    ANSI_ESCAPE_RE.get_or_init(|| Regex::new(r"\x1b\[[0-9;]*m").unwrap())
-}
-
 fn get_failures_map() -> &'static Mutex<HashMap<u64, bool>> {
     DERIVATION_FAILURES.get_or_init(|| Mutex::new(HashMap::new()))
 }
@@ -48,10 +50,15 @@ fn remove_active_derivation(id: u64) {
     }
 }
 
+// A derivation mentioned in a message, the name without store directory and .drv captured
+pub(crate) fn derivation_re() -> &'static regex::Regex {
+    static DERIVATION_RE: OnceLock<regex::Regex> = OnceLock::new();
+    DERIVATION_RE
+        .get_or_init(|| regex::Regex::new(r#"/nix/store/([a-zA-Z0-9_.+-]+)\.drv"#).unwrap())
+}
+
 fn find_derivation_id_for_error(msg: &str) -> Option<u64> {
-    // Use the same regex pattern as in logs.rs to extract derivation names from error messages
-    let re = regex::Regex::new(r#"/nix/store/([a-zA-Z0-9_.+-]+)\.drv"#).ok()?;
-    let captures = re.captures(msg)?;
+    let captures = derivation_re().captures(msg)?;
     let capture = captures.get(1)?.as_str();
 
     // Build the derivation name in the same format as logs.rs
@@ -89,6 +96,38 @@ fn remove_derivation_tracking(id: u64) {
     }
 }
 
+// The build activity of the first derivation mentioned in a message, unlike
+// find_derivation_id_for_error also after the activity stopped
+fn derivation_id_in(msg: &str, logone: &logone::LogOne) -> Option<u64> {
+    let drv = derivation_re().find(msg)?.as_str();
+    logone
+        .drv_to_id
+        .get(&format!("building '{}'", drv))
+        .copied()
+}
+
+// Rules of the Filter for the derivation of a build activity
+fn shows_derivation(id: u64, logone: &logone::LogOne) -> bool {
+    match logone.id_to_drv.get(&id) {
+        Some(drv) => logone
+            .filter()
+            .shows_derivation(&logone::derivation_name(drv)),
+        None => true,
+    }
+}
+
+fn always_shows(id: u64, logone: &logone::LogOne) -> bool {
+    match logone.id_to_drv.get(&id) {
+        Some(drv) => logone.filter().always_shows(&logone::derivation_name(drv)),
+        None => false,
+    }
+}
+
+// Derivations with @cargo sessions, their output is shown in cargo style by hybrid mode
+fn is_cargo_derivation(id: u64, log_level: LogLevel, logone: &logone::LogOne) -> bool {
+    log_level == LogLevel::Hybrid && logone.cargo_log_buffers_state.contains_key(&id)
+}
+
 fn check_stop_for_failure(obj: &Map<String, Value>) -> bool {
     // Check for exit codes - non-zero typically indicates failure
     if let Some(exit_code) = obj.get("exitCode").and_then(|v| v.as_i64()) {
@@ -126,28 +165,325 @@ fn check_stop_for_failure(obj: &Map<String, Value>) -> bool {
     false
 }
 
+// Literal escape sequences make the JSON invalid, when colours are kept the
+// SGR ones are turned into JSON escapes instead of being dropped
+fn clean_json_line(json: &str, keep_colors: bool) -> Cow<'_, str> {
+    if keep_colors && json.contains('\x1b') {
+        Cow::Owned(sanitize_ansi(json).replace('\x1b', "\\u001b"))
+    } else {
+        strip_ansi(json)
+    }
+}
+
+// Escape sequences in the decoded strings, nix escapes ESC as \u001b in its JSON.
+// Colours are kept unless the output has none at all.
+fn clean_json_strings(value: &mut Value, colored: bool) {
+    match value {
+        Value::String(s) => {
+            let cleaned = if colored {
+                sanitize_ansi(s)
+            } else {
+                strip_ansi(s)
+            };
+            if let Cow::Owned(cleaned) = cleaned {
+                *s = cleaned;
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|v| clean_json_strings(v, colored)),
+        Value::Object(map) => map
+            .values_mut()
+            .for_each(|v| clean_json_strings(v, colored)),
+        _ => {}
+    }
+}
+
+// Start and stop of build activities (type 105)
+fn track_timing(action: &str, message_type: Option<u64>, id: Id, logone: &mut logone::LogOne) {
+    let line = logone.line_number();
+    match (action, message_type) {
+        ("start", Some(105)) => {
+            logone.timings.insert(
+                id,
+                logone::Timing {
+                    started: Utc::now(),
+                    started_line: line,
+                    stopped: None,
+                    stopped_line: None,
+                },
+            );
+        }
+        ("stop", _) => {
+            if let Some(timing) = logone.timings.get_mut(&id) {
+                timing.stopped = Some(Utc::now());
+                timing.stopped_line = Some(line);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Start, phases and stop of every activity for traces
+fn track_span(
+    obj: &Map<String, Value>,
+    action: &str,
+    id: Id,
+    activity: &logone::Activity,
+    logone: &mut logone::LogOne,
+) {
+    match action {
+        "start" => {
+            let fields = obj
+                .get("fields")
+                .and_then(|fields| fields.as_array())
+                .cloned()
+                .unwrap_or_default();
+            logone.spans.insert(
+                id,
+                logone::ActivitySpan {
+                    activity: activity.clone(),
+                    fields,
+                    started: Utc::now(),
+                    stopped: None,
+                    phases: Vec::new(),
+                },
+            );
+        }
+        "result" if obj.get("type").and_then(|v| v.as_u64()) == Some(104) => {
+            let phase = obj
+                .get("fields")
+                .and_then(|fields| fields.get(0))
+                .and_then(|phase| phase.as_str());
+            if let (Some(span), Some(phase)) = (logone.spans.get_mut(&id), phase) {
+                span.phases.push((Utc::now(), phase.to_string()));
+            }
+        }
+        "stop" => {
+            if let Some(span) = logone.spans.get_mut(&id) {
+                span.stopped.get_or_insert_with(Utc::now);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The derivation of build activities (type 105), from
+// fields [drv, machine, round, nrRounds] or the "building '...drv'" text
+fn track_derivation(obj: &Map<String, Value>, id: Id, logone: &mut logone::LogOne) {
+    let drv = obj
+        .get("fields")
+        .and_then(|fields| fields.get(0))
+        .and_then(|drv| drv.as_str())
+        .filter(|drv| drv.ends_with(".drv"))
+        .map(|drv| drv.to_string())
+        .or_else(|| {
+            let text = obj.get("text").and_then(|v| v.as_str())?;
+            let start = text.find("/nix/store/")?;
+            let end = text[start..].find(".drv")? + start + ".drv".len();
+            Some(text[start..end].to_string())
+        });
+    if let Some(drv) = drv {
+        logone.id_to_drv.insert(id, drv);
+    }
+}
+
+// "builder for '...drv' failed with exit code 1" or "... failed due to signal 9 (Killed)",
+// newer nix says "Cannot build '...drv'. Reason: builder failed with exit code 1"
+fn track_failure(obj: &Map<String, Value>, logone: &mut logone::LogOne) {
+    static FAILURE_RE: OnceLock<regex::Regex> = OnceLock::new();
+    let re = FAILURE_RE.get_or_init(|| {
+        regex::Regex::new(
+            r"(/nix/store/[a-zA-Z0-9_.+-]+\.drv)'?\.? (?:Reason: builder )?failed (?:with exit code (\d+)|due to signal (\d+))",
+        )
+        .unwrap()
+    });
+    if obj.get("level").and_then(|v| v.as_u64()) != Some(0) {
+        return;
+    }
+    // nix colours the derivation path
+    let msg = strip_ansi(obj.get("msg").and_then(|v| v.as_str()).unwrap_or(""));
+    let captures = match re.captures(&msg) {
+        Some(captures) => captures,
+        None => return,
+    };
+    let drv = &captures[1];
+    let id = logone
+        .id_to_drv
+        .iter()
+        .find(|(_, d)| d.as_str() == drv)
+        .map(|(&id, _)| id);
+    let exit_status = match (captures.get(2), captures.get(3)) {
+        (Some(code), _) => ExitStatus::from_value(&Value::String(code.as_str().to_string())),
+        (None, Some(signal)) => signal
+            .as_str()
+            .parse()
+            .map(ExitStatus::Signal)
+            .unwrap_or(ExitStatus::Unknown),
+        (None, None) => ExitStatus::Unknown,
+    };
+    if let Some(id) = id {
+        logone.record_build_failure(id, exit_status);
+        cargo_logs::handle_builder_failure(id, exit_status, logone);
+    }
+}
+
+// Progress (result type 105) of downloads (type 101) below a substitution (type 108),
+// fields [done, expected, running, failed] in bytes
+fn track_substitution(obj: &Map<String, Value>, id: Id, logone: &mut logone::LogOne) {
+    let is_transfer =
+        |id: &Id| logone.activities.get(id).and_then(|a| a.activity_type) == Some(101);
+    if !is_transfer(&id) {
+        return;
+    }
+    let mut parent = logone.activities.get(&id).and_then(|a| a.parent);
+    while let Some(activity) = parent.and_then(|parent| logone.activities.get(&parent)) {
+        if activity.activity_type == Some(108) {
+            let done = obj
+                .get("fields")
+                .and_then(|fields| fields.get(0))
+                .and_then(|done| done.as_u64())
+                .unwrap_or(0);
+            logone.substituted_bytes.insert(id, done);
+            return;
+        }
+        parent = activity.parent;
+    }
+}
+
+// Build log lines (result type 101) for reports
+fn retain_log_line(obj: &Map<String, Value>, id: Id, logone: &mut logone::LogOne) {
+    if !logone.retains_logs() || !logone.id_to_drv.contains_key(&id) {
+        return;
+    }
+    let line = obj
+        .get("fields")
+        .and_then(|fields| fields.get(0))
+        .and_then(|line| line.as_str())
+        .unwrap_or("");
+    logone
+        .build_logs
+        .entry(id)
+        .or_default()
+        .push(line.to_string());
+}
+
+// Events of build activities and nix messages
+fn emit_events(
+    obj: &Map<String, Value>,
+    action: &str,
+    message_type: Option<u64>,
+    logone: &mut logone::LogOne,
+) {
+    if !logone.has_event_handlers() {
+        return;
+    }
+    if action == "start" {
+        logone.flush_stopped_builds();
+    }
+    let id = obj.get("id").and_then(|v| v.as_u64());
+    let field = || {
+        obj.get("fields")
+            .and_then(|fields| fields.get(0))
+            .and_then(|field| field.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    match (action, message_type, id) {
+        ("start", Some(105), Some(id)) => {
+            if let Some(drv) = logone.id_to_drv.get(&id).cloned() {
+                logone.emit(Event::BuildStarted { id, drv });
+            }
+        }
+        ("result", Some(101), Some(id)) => {
+            let line = field();
+            if !line.starts_with("@cargo") {
+                logone.emit(Event::LogLine { id, line });
+            }
+        }
+        ("result", Some(104), Some(id)) => {
+            logone.emit(Event::Phase { id, phase: field() });
+        }
+        ("stop", _, Some(id)) => logone.build_stopped(id),
+        ("msg", _, _) => {
+            let msg = obj.get("msg").and_then(|v| v.as_str()).unwrap_or("");
+            let level = obj.get("level").and_then(|v| v.as_u64());
+            // errors which are not about a derivation, e.g. of the evaluation
+            if level == Some(0) && strip_ansi(msg).starts_with("error:") && !msg.contains(".drv") {
+                logone.emit(Event::EvalError {
+                    message: strip_ansi(msg).into_owned(),
+                    file: obj.get("file").and_then(|v| v.as_str()).map(String::from),
+                    line: obj.get("line").and_then(|v| v.as_u64()),
+                    column: obj.get("column").and_then(|v| v.as_u64()),
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn get_id(obj: &Map<String, Value>, action: &'static str) -> Result<Id> {
+    obj.get("id")
+        .and_then(|v| v.as_u64())
+        .ok_or(LogoneError::MissingField {
+            field: "id",
+            action,
+        })
+}
+
+/// Parses one line of `nix build --log-format internal-json` output.
+///
+/// Errors carry the line number (counted per `LogOne`) and the raw line.
 pub fn parse_nix_line(line: &str, logone: &mut logone::LogOne) -> Result<()> {
+    let line_number = logone.next_line();
+    let result = parse_line(line, logone).map_err(|e| e.at_line(line_number, line));
+
+    for warning in logone.take_warnings() {
+        logone.diagnose(&warning.at_line(line_number, line));
+    }
+    if let Err(e) = &result {
+        logone.diagnose(e);
+    }
+    result
+}
+
+// Activity and result types as defined in nix's logging.hh, @cargo types 0 to 3
+fn is_known_message(action: &str, message_type: Option<u64>) -> bool {
+    matches!(
+        (action, message_type),
+        ("start", Some(0 | 100..=112))
+            | ("result", Some(100..=108))
+            | ("stop", _)
+            | ("msg", _)
+            | ("cargo", Some(0..=3))
+    )
+}
+
+fn parse_line(line: &str, logone: &mut logone::LogOne) -> Result<()> {
     let json_content = if let Some(content) = line.strip_prefix("@nix ") {
         content
     } else {
-        // Not a JSON line, ignore
+        // Not a JSON line, output from nix or a wrapper script
+        logone.other_line(line);
         return Ok(());
     };
     // Clean ANSI escape sequences
-    let clean_content = get_ansi_regex().replace_all(json_content, "");
+    let keep_colors = logone.keep_colors();
+    let clean_content = clean_json_line(json_content, keep_colors);
 
     // Parse as fuzzy JSON - no fixed structs but enforce "action" field
-    let value: Value =
-        serde_json::from_str(&clean_content).map_err(|e| anyhow!("JSON parse error: {}", e))?;
+    let mut value: Value = serde_json::from_str(&clean_content)?;
+    clean_json_strings(&mut value, logone.colored);
 
-    let obj: &Map<String, Value> = value
-        .as_object()
-        .ok_or_else(|| anyhow!("Expected JSON object"))?;
+    let obj: &Map<String, Value> = value.as_object().ok_or(LogoneError::NotAnObject)?;
 
-    let action: &str = obj
-        .get("action")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("Missing or invalid 'action' field"))?;
+    let action: &str =
+        obj.get("action")
+            .and_then(|v| v.as_str())
+            .ok_or(LogoneError::MissingField {
+                field: "action",
+                action: "@nix message",
+            })?;
 
     let message_type: Option<u64> = obj.get("type").and_then(|v| v.as_u64());
     // check for embedded @cargo log message
@@ -167,11 +503,11 @@ pub fn parse_nix_line(line: &str, logone: &mut logone::LogOne) -> Result<()> {
         if content.starts_with("@cargo") {
             // Only process @cargo messages in "cargo" mode
             match logone.level() {
-                LogLevel::Cargo => {
-                    let id = obj
-                        .get("id")
-                        .and_then(|v| v.as_u64())
-                        .ok_or_else(|| anyhow!("Missing id in log line"))?;
+                LogLevel::Cargo | LogLevel::Hybrid => {
+                    let id = get_id(obj, "@cargo log line")?;
+                    if !shows_derivation(id, logone) {
+                        return Ok(());
+                    }
                     return crate::parser::parse_cargo_line(id, &content, logone);
                 }
                 LogLevel::Errors | LogLevel::Verbose => {
@@ -192,15 +528,14 @@ pub fn parse_cargo_line(id: u64, line: &str, logone: &mut logone::LogOne) -> Res
     };
 
     // Clean ANSI escape sequences
-    let clean_content = get_ansi_regex().replace_all(json_content, "");
+    let keep_colors = logone.keep_colors();
+    let clean_content = clean_json_line(json_content, keep_colors);
 
     // Parse as fuzzy JSON - no fixed structs but enforce "action" field
-    let mut value: Value =
-        serde_json::from_str(&clean_content).map_err(|e| anyhow!("JSON parse error: {}", e))?;
+    let mut value: Value = serde_json::from_str(&clean_content)?;
+    clean_json_strings(&mut value, logone.colored);
 
-    let obj: &mut Map<String, Value> = value
-        .as_object_mut()
-        .ok_or_else(|| anyhow!("Expected JSON object"))?;
+    let obj: &mut Map<String, Value> = value.as_object_mut().ok_or(LogoneError::NotAnObject)?;
 
     obj.insert("id".to_string(), id.into());
     // Get optional type field
@@ -208,6 +543,11 @@ pub fn parse_cargo_line(id: u64, line: &str, logone: &mut logone::LogOne) -> Res
     process_event(obj, "cargo", message_type, logone)
 }
 
+/// Routes a message to the sinks of the current `LogLevel`.
+///
+/// Before that, activities, spans, build timings, derivations, failures, substitutions
+/// and retained logs are tracked the same way in every log level: reports and events describe the
+/// whole build, not just what the level prints.
 pub fn process_event(
     obj: &Map<String, Value>,
     action: &str,
@@ -217,6 +557,43 @@ pub fn process_event(
     // Apply filtering based on log level
     let log_level = logone.level();
 
+    if !is_known_message(action, message_type) {
+        logone.warn(LogoneError::UnknownMessage {
+            action: action.to_string(),
+            message_type,
+        });
+    }
+    if let Some(id) = obj.get("id").and_then(|v| v.as_u64()) {
+        let activity = logone::Activity {
+            activity_type: message_type,
+            text: obj
+                .get("text")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            parent: obj
+                .get("parent")
+                .and_then(|v| v.as_u64())
+                .filter(|&parent| parent != 0),
+        };
+        if logone.records_spans() {
+            track_span(obj, action, id, &activity, logone);
+        }
+        logone.track_activity(action, id, activity);
+        track_timing(action, message_type, id, logone);
+        match (action, message_type) {
+            ("start", Some(105)) => track_derivation(obj, id, logone),
+            ("result", Some(101)) => retain_log_line(obj, id, logone),
+            ("result", Some(105)) => track_substitution(obj, id, logone),
+            _ => {}
+        }
+    }
+
+    if action == "msg" {
+        track_failure(obj, logone);
+    }
+    emit_events(obj, action, message_type, logone);
+
     // Route based on action and type
     match (action, message_type) {
         // STATUS handling - type 104 starts, type 105 updates
@@ -224,27 +601,40 @@ pub fn process_event(
             nix_build_statistics::handle_status_start(obj, logone)?;
         }
         ("result", Some(105)) => {
-            nix_build_statistics::handle_status_update(obj, logone)?;
+            // type 105 results of other activities are download/copy progress
+            let id = get_id(obj, "stats update")?;
+            if nix_build_statistics::is_status_id(id) {
+                nix_build_statistics::handle_status_update(obj, logone)?;
+            }
         }
         ("stop", _) => {
             // Check if this is a status stop or log stop
             let id = obj.get("id").and_then(|v| v.as_u64());
             if let Some(id) = id {
+                if log_level.handles_cargo() {
+                    cargo_logs::handle_activity_stop(id, logone)?;
+                }
                 if nix_build_statistics::is_status_id(id) {
                     nix_build_statistics::handle_status_stop(obj, logone)?;
                 } else if nix_logs::has_log_buffer(id, logone) {
                     // Handle log stop based on log level and failure status
                     match log_level {
-                        LogLevel::Errors => {
+                        LogLevel::Errors | LogLevel::Hybrid => {
                             // Check if the stop payload itself indicates failure
                             let stop_indicates_failure = check_stop_for_failure(obj);
                             if stop_indicates_failure {
                                 mark_derivation_failed(id);
                             }
 
-                            // In errors mode, only flush logs if the derivation failed
-                            if is_derivation_failed(id) {
+                            // In errors mode, only flush logs if the derivation failed,
+                            // hybrid mode showed the cargo output of crate builds instead
+                            if is_derivation_failed(id)
+                                && !is_cargo_derivation(id, log_level, logone)
+                            {
+                                nix_logs::handle_log_stop(obj, logone)?;
+                            } else if always_shows(id, logone) {
                                 nix_logs::handle_log_stop(obj, logone)?;
+                                logone.print_log_buffer_by_id(id);
                             }
                             // For non-failed builds in errors mode, we skip handle_log_stop
                             // which effectively drops the buffer without printing
@@ -257,6 +647,9 @@ pub fn process_event(
                             // In verbose mode, always flush all logs
                             nix_logs::handle_log_stop(obj, logone)?;
                             remove_active_derivation(id);
+                            if logone.flush_order() == FlushOrder::Live {
+                                logone.print_log_buffer_by_id(id);
+                            }
                         }
                         LogLevel::Cargo => {
                             // In cargo mode, @nix logs are ignored anyway
@@ -273,7 +666,7 @@ pub fn process_event(
         ("start", Some(105)) => {
             // Only process @nix logs in "errors" and "verbose" modes
             match log_level {
-                LogLevel::Errors | LogLevel::Verbose => {
+                LogLevel::Errors | LogLevel::Verbose | LogLevel::Hybrid => {
                     nix_logs::handle_log_start(obj, logone)?;
 
                     // Track active derivation for proper failure attribution
@@ -290,7 +683,7 @@ pub fn process_event(
         ("result", Some(101)) => {
             // Only process @nix log lines in "errors" and "verbose" modes
             match log_level {
-                LogLevel::Errors | LogLevel::Verbose => {
+                LogLevel::Errors | LogLevel::Verbose | LogLevel::Hybrid => {
                     nix_logs::handle_log_line(obj, logone)?;
                 }
                 LogLevel::Cargo => {
@@ -301,7 +694,7 @@ pub fn process_event(
         ("result", Some(104)) => {
             // Only process @nix log phases in "errors" and "verbose" modes
             match log_level {
-                LogLevel::Errors | LogLevel::Verbose => {
+                LogLevel::Errors | LogLevel::Verbose | LogLevel::Hybrid => {
                     nix_logs::handle_log_phase(obj, logone)?;
                 }
                 LogLevel::Cargo => {
@@ -316,11 +709,38 @@ pub fn process_event(
             match log_level {
                 LogLevel::Cargo => {
                     // In "cargo" mode, suppress ALL @nix messages
-                    // Only @cargo messages should be processed in cargo mode
+                    // Only @cargo messages should be processed in cargo mode,
+                    // unless the filter asks for the level
+                    let level = obj.get("level").and_then(|v| v.as_u64()).unwrap_or(0);
+                    if logone.filter().shows_level(level, false) {
+                        let msg = obj.get("msg").and_then(|v| v.as_str()).unwrap_or("");
+                        let file = obj.get("file").and_then(|v| v.as_str());
+                        logone.print_message(level, msg, file);
+                    }
                 }
-                LogLevel::Errors => {
+                LogLevel::Errors | LogLevel::Hybrid => {
                     let msg = obj.get("msg").and_then(|v| v.as_str()).unwrap_or("");
                     let level = obj.get("level").and_then(|v| v.as_u64()).unwrap_or(0);
+                    let failing_id = find_derivation_id_for_error(msg);
+
+                    if log_level == LogLevel::Hybrid {
+                        match derivation_id_in(msg, logone) {
+                            // the crate's errors and a `nix log` hint were shown already
+                            Some(id) if is_cargo_derivation(id, log_level, logone) => {
+                                logone.nix_log_buffers.remove(&id);
+                                return Ok(());
+                            }
+                            // e.g. evaluation errors, which are not about a derivation
+                            None if level == 0 => {
+                                if logone.filter().shows_level(0, true) {
+                                    let file = obj.get("file").and_then(|v| v.as_str());
+                                    logone.print_message(0, msg, file);
+                                }
+                                return Ok(());
+                            }
+                            _ => {}
+                        }
+                    }
 
                     // Check if this message indicates a build failure
                     let is_error = level >= 3
@@ -334,7 +754,7 @@ pub fn process_event(
 
                     if is_error {
                         // Try to find the specific derivation this error belongs to
-                        if let Some(failing_id) = find_derivation_id_for_error(msg) {
+                        if let Some(failing_id) = failing_id {
                             // Mark only the specific failing derivation, not all active ones
                             mark_derivation_failed(failing_id);
                         }
@@ -343,6 +763,9 @@ pub fn process_event(
                         // incorrectly marking all active derivations as failed
 
                         nix_logs::handle_msg(obj, logone)?;
+                    } else if logone.filter().shows_level(level, false) {
+                        let file = obj.get("file").and_then(|v| v.as_str());
+                        logone.print_message(level, msg, file);
                     }
                 }
                 LogLevel::Verbose => {
@@ -356,31 +779,30 @@ pub fn process_event(
         ("cargo", Some(0)) => {
             // Only process @cargo messages in "cargo" mode
             match log_level {
-                LogLevel::Cargo => {
-                    cargo_logs::handle_cargo_log_start(obj, logone)?;
+                LogLevel::Cargo | LogLevel::Hybrid => {
+                    cargo_logs::handle_session_start(obj, logone)?;
                 }
                 LogLevel::Errors | LogLevel::Verbose => {
                     // In "errors" and "verbose" modes, ignore @cargo messages
                 }
             }
         }
-        ("cargo", Some(2)) => {
+        ("cargo", Some(1)) => {
             // Only process @cargo messages in "cargo" mode
             match log_level {
-                LogLevel::Cargo => {
-                    cargo_logs::handle_cargo_log_rustc_exit(obj, logone)?;
+                LogLevel::Cargo | LogLevel::Hybrid => {
+                    cargo_logs::handle_session_message(obj, logone)?;
                 }
                 LogLevel::Errors | LogLevel::Verbose => {
                     // In "errors" and "verbose" modes, ignore @cargo messages
                 }
             }
         }
-
-        ("cargo", Some(3)) => {
+        ("cargo", Some(2 | 3)) => {
             // Only process @cargo messages in "cargo" mode
             match log_level {
-                LogLevel::Cargo => {
-                    cargo_logs::handle_cargo_log_build_exit(obj, logone)?;
+                LogLevel::Cargo | LogLevel::Hybrid => {
+                    cargo_logs::handle_session_end(obj, logone)?;
                 }
                 LogLevel::Errors | LogLevel::Verbose => {
                     // In "errors" and "verbose" modes, ignore @cargo messages
@@ -393,3 +815,122 @@ pub fn process_event(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LogoneError;
+
+    #[test]
+    fn typed_errors() {
+        let mut logone = logone::LogOne::new(false, LogLevel::Errors);
+        let error = parse_nix_line("@nix {\"action\":", &mut logone).unwrap_err();
+        assert!(matches!(error.kind(), LogoneError::InvalidJson(_)));
+        assert_eq!(error.line(), Some(1));
+        let error = parse_nix_line("@nix [1]", &mut logone).unwrap_err();
+        assert!(matches!(error.kind(), LogoneError::NotAnObject));
+        assert_eq!(error.raw(), Some("@nix [1]"));
+        let error = parse_nix_line("@nix {\"id\":1}", &mut logone).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            LogoneError::MissingField {
+                field: "action",
+                ..
+            }
+        ));
+        assert_eq!(error.line(), Some(3));
+        // other lines are not parsed
+        assert!(parse_nix_line("warning: unknown setting", &mut logone).is_ok());
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut logone = logone::LogOne::new(false, LogLevel::Errors);
+        for line in [
+            r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"","type":0}"#,
+            r#"@nix {"action":"start","id":1,"level":3,"parent":0,"text":"","type":0}"#,
+            r#"@nix {"action":"result","id":2,"type":101,"fields":["a line"]}"#,
+            r#"@nix {"action":"stop","id":1}"#,
+            r#"@nix {"action":"stop","id":1}"#,
+            r#"@nix {"action":"start","id":3,"level":3,"parent":0,"text":"","type":199}"#,
+            r#"@nix {"action":"#,
+        ] {
+            let _ = parse_nix_line(line, &mut logone);
+        }
+        let counts: Vec<(&str, usize)> = logone
+            .diagnostics
+            .counts()
+            .iter()
+            .map(|(category, count)| (*category, *count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("duplicate-start", 1),
+                ("invalid-json", 1),
+                ("unknown-activity", 2),
+                ("unknown-message", 1),
+            ]
+        );
+    }
+
+    fn build_log_line(logone: &mut logone::LogOne, id: Id, json_line: &str) -> String {
+        let start = format!(
+            r#"@nix {{"action":"start","id":{},"level":3,"parent":0,"text":"building '/nix/store/0a-hello.drv'","type":105}}"#,
+            id
+        );
+        parse_nix_line(&start, logone).unwrap();
+        let result = format!(
+            r#"@nix {{"action":"result","id":{},"type":101,"fields":["{}"]}}"#,
+            id, json_line
+        );
+        parse_nix_line(&result, logone).unwrap();
+        logone.nix_log_buffers[&id][0].content.clone()
+    }
+
+    #[test]
+    fn colours_of_decoded_strings() {
+        let line = r"\u001b[31mred\u001b[0m\u001b[2K done\r";
+        let mut logone = logone::LogOne::new(true, LogLevel::Verbose);
+        assert_eq!(
+            build_log_line(&mut logone, 30_001, line),
+            "\x1b[31mred\x1b[0m done"
+        );
+        let mut logone = logone::LogOne::new(false, LogLevel::Verbose);
+        assert_eq!(build_log_line(&mut logone, 30_002, line), "red done");
+    }
+
+    #[test]
+    fn derivations_in_every_level() {
+        let drv = "/nix/store/0aa-serde-1_0_218-472e28b9f131b02c.drv";
+        for (level, id) in [
+            (LogLevel::Errors, 38_001),
+            (LogLevel::Verbose, 38_002),
+            (LogLevel::Cargo, 38_003),
+        ] {
+            let mut logone = logone::LogOne::new(false, level);
+            let from_fields = format!(
+                r#"@nix {{"action":"start","id":{},"level":3,"parent":0,"text":"","type":105,"fields":["{}","",1,1]}}"#,
+                id, drv
+            );
+            let from_text = format!(
+                r#"@nix {{"action":"start","id":{},"level":3,"parent":0,"text":"building '{}'","type":105}}"#,
+                id + 10,
+                drv.replace("serde", "syn")
+            );
+            let cargo_start = format!(
+                r#"@nix {{"action":"result","id":{},"type":101,"fields":["@cargo {{\"type\":0,\"crate_name\":\"serde\",\"crate_type\":\"(lib)\"}}"]}}"#,
+                id
+            );
+            for line in [&from_fields, &from_text, &cargo_start] {
+                parse_nix_line(line, &mut logone).unwrap();
+            }
+            assert_eq!(logone.id_to_drv[&id], drv);
+            assert_eq!(logone.id_to_drv[&(id + 10)], drv.replace("serde", "syn"));
+            assert_eq!(logone.timings[&id].started_line, 1);
+            if level == LogLevel::Cargo {
+                assert_eq!(logone.cargo_sessions[&id].derivation.as_deref(), Some(drv));
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use super::{builds, seconds, sessions, BuildReport, Outcome};
+use crate::ansi::ansi_to_html;
+use crate::logone::LogOne;
+use chrono::{DateTime, Utc};
+use std::io::{self, Write};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.4em; }
+.stats span { display: inline-block; margin-right: 2em; font-size: 1.2em; }
+.success { color: #0a7d32; } .failure { color: #c0162c; } .unfinished { color: #b36b00; }
+table { border-collapse: collapse; width: 100%; margin: 1em 0 2em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; }
+th { cursor: pointer; user-select: none; background: #f4f4f4; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+.timeline { position: relative; margin-bottom: 2em; }
+.row { position: relative; height: 1.3em; margin: 2px 0; }
+.row .label { position: absolute; left: 0; width: 25%; overflow: hidden; white-space: nowrap; font-size: 0.8em; }
+.row .track { position: absolute; left: 26%; right: 0; top: 0; bottom: 0; background: #f7f7f7; }
+.bar { position: absolute; top: 0; bottom: 0; min-width: 2px; }
+.bar.success { background: #5cb85c; } .bar.failure { background: #d9534f; } .bar.unfinished { background: #f0ad4e; }
+details { margin: 0.3em 0; }
+summary { cursor: pointer; }
+pre { background: #1e1e1e; color: #ddd; padding: 0.8em; overflow-x: auto; font-size: 0.85em; }
+"#;
+
+// sorts the table by the clicked column, numbers by data-sort
+const SCRIPT: &str = r#"
+document.querySelectorAll('th').forEach(function (th, column) {
+  th.addEventListener('click', function () {
+    var tbody = th.closest('table').tBodies[0];
+    var ascending = th.dataset.order !== 'asc';
+    th.dataset.order = ascending ? 'asc' : 'desc';
+    var key = function (row) {
+      var cell = row.cells[column];
+      return cell.dataset.sort !== undefined ? parseFloat(cell.dataset.sort) : cell.textContent;
+    };
+    Array.from(tbody.rows)
+      .sort(function (a, b) {
+        var x = key(a), y = key(b);
+        return (x < y ? -1 : x > y ? 1 : 0) * (ascending ? 1 : -1);
+      })
+      .forEach(function (row) { tbody.appendChild(row); });
+  });
+});
+"#;
+
+fn escape(text: &str) -> String {
+    ansi_to_html(&crate::ansi::strip_ansi(text))
+}
+
+fn timeline(out: &mut impl Write, builds: &[BuildReport]) -> io::Result<()> {
+    let start = match builds.iter().map(|build| build.started).min() {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    let end: DateTime<Utc> = builds
+        .iter()
+        .map(|build| build.started + build.duration.unwrap_or_default())
+        .max()
+        .unwrap_or(start);
+    let total = (end - start).num_microseconds().unwrap_or(1).max(1) as f64;
+
+    writeln!(out, "<h2>Timeline</h2>\n<div class=\"timeline\">")?;
+    for build in builds {
+        let offset = (build.started - start).num_microseconds().unwrap_or(0) as f64;
+        let length = match build.duration {
+            Some(duration) => duration.num_microseconds().unwrap_or(0) as f64,
+            None => total - offset,
+        };
+        writeln!(
+            out,
+            "<div class=\"row\"><div class=\"label\">{}</div><div class=\"track\"><div class=\"bar {}\" style=\"left:{:.2}%;width:{:.2}%\" title=\"{} ({:.1}s)\"></div></div></div>",
+            escape(&build.name),
+            build.outcome.name(),
+            offset / total * 100.0,
+            length / total * 100.0,
+            escape(&build.name),
+            seconds(build.duration),
+        )?;
+    }
+    writeln!(out, "</div>")
+}
+
+fn log(out: &mut impl Write, title: &str, outcome: Outcome, lines: &[String]) -> io::Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    // failed builds are opened
+    let open = if outcome == Outcome::Failure {
+        " open"
+    } else {
+        ""
+    };
+    writeln!(
+        out,
+        "<details{}><summary class=\"{}\">{}</summary><pre>",
+        open,
+        outcome.name(),
+        escape(title)
+    )?;
+    for line in lines {
+        writeln!(out, "{}", ansi_to_html(line))?;
+    }
+    writeln!(out, "</pre></details>")
+}
+
+/// Writes a single HTML file without external resources: totals, a sortable table and
+/// a timeline of the derivations and the build logs with their colours.
+///
+/// Build logs are only included if they were kept with `LogOne::with_retained_logs`.
+pub fn write_html(logone: &LogOne, out: &mut impl Write) -> io::Result<()> {
+    let builds = builds(logone);
+    let sessions = sessions(logone);
+    let count = |outcome: Outcome| builds.iter().filter(|b| b.outcome == outcome).count();
+    let wall_time = builds
+        .iter()
+        .map(|build| build.started)
+        .min()
+        .zip(
+            builds
+                .iter()
+                .filter_map(|build| build.duration.map(|duration| build.started + duration))
+                .max(),
+        )
+        .map(|(start, end)| end - start);
+
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(
+        out,
+        "<title>logone build report</title>\n<style>{}</style>\n</head>\n<body>",
+        STYLE
+    )?;
+    writeln!(out, "<h1>Build report</h1>\n<div class=\"stats\">")?;
+    writeln!(
+        out,
+        "<span class=\"success\">{} done</span>",
+        count(Outcome::Success)
+    )?;
+    writeln!(
+        out,
+        "<span class=\"failure\">{} failed</span>",
+        count(Outcome::Failure)
+    )?;
+    if count(Outcome::Unfinished) > 0 {
+        writeln!(
+            out,
+            "<span class=\"unfinished\">{} unfinished</span>",
+            count(Outcome::Unfinished)
+        )?;
+    }
+    if let Some((_, expected, _, _)) = logone.stats() {
+        writeln!(out, "<span>{} expected</span>", expected)?;
+    }
+    writeln!(out, "<span>{:.1}s</span>\n</div>", seconds(wall_time))?;
+
+    writeln!(out, "<h2>Derivations</h2>\n<table>")?;
+    writeln!(
+        out,
+        "<thead><tr><th>Derivation</th><th>Status</th><th>Duration (s)</th><th>Exit status</th></tr></thead>\n<tbody>"
+    )?;
+    for build in &builds {
+        writeln!(
+            out,
+            "<tr><td title=\"{}\">{}</td><td class=\"{}\">{}</td><td class=\"num\" data-sort=\"{}\">{:.1}</td><td>{}</td></tr>",
+            escape(&build.drv),
+            escape(&build.name),
+            build.outcome.name(),
+            build.outcome.name(),
+            seconds(build.duration),
+            seconds(build.duration),
+            build
+                .exit_status
+                .map(|status| status.to_string())
+                .unwrap_or_default(),
+        )?;
+    }
+    writeln!(out, "</tbody>\n</table>")?;
+
+    if !sessions.is_empty() {
+        writeln!(out, "<h2>Crates</h2>\n<table>")?;
+        writeln!(
+            out,
+            "<thead><tr><th>Crate</th><th>Target</th><th>Status</th><th>Duration (s)</th><th>Warnings</th></tr></thead>\n<tbody>"
+        )?;
+        for report in &sessions {
+            let warnings = report
+                .session
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.is_warning() && !diagnostic.is_summary())
+                .count();
+            writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td class=\"num\" data-sort=\"{}\">{:.1}</td><td class=\"num\" data-sort=\"{}\">{}</td></tr>",
+                escape(&report.session.package_name()),
+                escape(&report.session.crate_type),
+                report.outcome.name(),
+                report.outcome.name(),
+                seconds(report.duration()),
+                seconds(report.duration()),
+                warnings,
+                warnings,
+            )?;
+        }
+        writeln!(out, "</tbody>\n</table>")?;
+    }
+
+    timeline(out, &builds)?;
+
+    writeln!(out, "<h2>Logs</h2>")?;
+    for build in &builds {
+        log(out, &build.name, build.outcome, &build.log)?;
+    }
+    for report in &sessions {
+        log(
+            out,
+            &report.session.target_name(),
+            report.outcome,
+            &report.session.messages,
+        )?;
+    }
+
+    writeln!(out, "<script>{}</script>\n</body>\n</html>", SCRIPT)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Generator;
+    use crate::logone::LogLevel;
+    use crate::parser::parse_nix_line;
+
+    fn html(gen: &Generator) -> String {
+        let mut logone = LogOne::new(true, LogLevel::Cargo).with_retained_logs(true);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        let mut out = Vec::new();
+        write_html(&logone, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escaping() {
+        let mut gen = Generator::new(145);
+        let build = gen.build_start("a<b>&\"c\"-1.0");
+        gen.log_line(build.id, "<script>alert(1)</script>");
+        gen.stop(build.id);
+
+        let html = html(&gen);
+        assert!(!html.contains("<b>"));
+        assert!(!html.contains("<script>alert"));
+        assert!(html.contains(
+            r#"-a&lt;b&gt;&amp;&quot;c&quot;-1.0.drv">a&lt;b&gt;&amp;&quot;c&quot;-1.0</td>"#
+        ));
+        assert!(html.contains(r#"title="a&lt;b&gt;&amp;&quot;c&quot;-1.0 ("#));
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;\n</pre>"));
+    }
+
+    #[test]
+    fn logs() {
+        let mut gen = Generator::new(146);
+        let hello = gen.build_start("hello-2.12.1");
+        gen.log_line(hello.id, "\x1b[32mPASS\x1b[0m: test-hello");
+        gen.stop(hello.id);
+        let failed = gen.build_start("bash-5.2p37");
+        gen.log_line(
+            failed.id,
+            "\x1b[1;31merror:\x1b[0m \x1b[2Kmissing <stdio.h>",
+        );
+        gen.stop(failed.id);
+        gen.build_failed(&failed, 2, &["error: missing <stdio.h>"]);
+
+        let html = html(&gen);
+        assert!(html.contains(
+            "<details><summary class=\"success\">hello-2.12.1</summary><pre>\n\
+             <span style=\"color:#0dbc79\">PASS</span>: test-hello\n</pre></details>"
+        ));
+        // failed builds are opened
+        assert!(html.contains(
+            "<details open><summary class=\"failure\">bash-5.2p37</summary><pre>\n\
+             <span style=\"font-weight:bold;color:#cd3131\">error:</span> missing &lt;stdio.h&gt;\n"
+        ));
+        assert!(!html.contains('\x1b'));
+    }
+}
@@ -0,0 +1,190 @@
+use super::{builds, seconds, sessions, tail, Outcome};
+use crate::ansi::strip_ansi;
+use crate::logone::LogOne;
+use std::io::{self, Write};
+
+/// Lines of the log put into a `<failure>`, as many as nix shows by default.
+const FAILURE_LINES: usize = 25;
+
+struct TestCase {
+    name: String,
+    classname: String,
+    time: f64,
+    outcome: Outcome,
+    failure: String,
+    log: Vec<String>,
+}
+
+/// Writes a JUnit XML report: a test case for every derivation and, in the levels
+/// processing `@cargo` messages, one for every crate session.
+///
+/// Build logs are only included if they were kept with `LogOne::with_retained_logs`.
+pub fn write_junit(logone: &LogOne, out: &mut impl Write) -> io::Result<()> {
+    let derivations: Vec<TestCase> = builds(logone)
+        .into_iter()
+        .map(|build| TestCase {
+            name: build.name,
+            classname: build.drv,
+            time: seconds(build.duration),
+            outcome: build.outcome,
+            failure: build
+                .exit_status
+                .map(|status| format!("builder failed with {}", status))
+                .unwrap_or_default(),
+            log: build.log,
+        })
+        .collect();
+    let crates: Vec<TestCase> = sessions(logone)
+        .into_iter()
+        .map(|report| TestCase {
+            name: report.session.target_name(),
+            classname: report.session.package_name(),
+            time: seconds(report.duration()),
+            outcome: report.outcome,
+            failure: report
+                .session
+                .exit_status
+                .map(|status| format!("{} failed with {}", report.session.target_name(), status))
+                .unwrap_or_default(),
+            log: report.session.messages,
+        })
+        .collect();
+
+    let all = derivations.iter().chain(crates.iter());
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites name="logone" tests="{}" failures="{}" errors="{}" time="{:.3}">"#,
+        derivations.len() + crates.len(),
+        count(all.clone(), Outcome::Failure),
+        count(all, Outcome::Unfinished),
+        derivations.iter().map(|case| case.time).sum::<f64>(),
+    )?;
+    write_suite(out, "nix", &derivations)?;
+    if !crates.is_empty() {
+        write_suite(out, "cargo", &crates)?;
+    }
+    writeln!(out, "</testsuites>")?;
+    out.flush()
+}
+
+fn count<'a>(cases: impl Iterator<Item = &'a TestCase>, outcome: Outcome) -> usize {
+    cases.filter(|case| case.outcome == outcome).count()
+}
+
+fn write_suite(out: &mut impl Write, name: &str, cases: &[TestCase]) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" time="{:.3}">"#,
+        name,
+        cases.len(),
+        count(cases.iter(), Outcome::Failure),
+        count(cases.iter(), Outcome::Unfinished),
+        cases.iter().map(|case| case.time).sum::<f64>(),
+    )?;
+    for case in cases {
+        writeln!(
+            out,
+            r#"    <testcase name="{}" classname="{}" time="{:.3}">"#,
+            escape(&case.name),
+            escape(&case.classname),
+            case.time
+        )?;
+        match case.outcome {
+            Outcome::Success => {}
+            Outcome::Failure => writeln!(
+                out,
+                r#"      <failure message="{}">{}</failure>"#,
+                escape(&case.failure),
+                escape(&tail(&case.log, FAILURE_LINES).join("\n"))
+            )?,
+            Outcome::Unfinished => writeln!(out, r#"      <error message="did not finish"/>"#)?,
+        }
+        if !case.log.is_empty() {
+            writeln!(
+                out,
+                "      <system-out>{}</system-out>",
+                escape(&case.log.join("\n"))
+            )?;
+        }
+        writeln!(out, "    </testcase>")?;
+    }
+    writeln!(out, "  </testsuite>")
+}
+
+// XML 1.0 does not allow escape sequences and most other control characters
+fn escape(text: &str) -> String {
+    strip_ansi(text)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Generator;
+    use crate::logone::LogLevel;
+    use crate::parser::parse_nix_line;
+    use serde_json::json;
+
+    fn junit(gen: &Generator) -> String {
+        let mut logone = LogOne::new(true, LogLevel::Cargo).with_retained_logs(true);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        let mut out = Vec::new();
+        write_junit(&logone, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escaping() {
+        let mut gen = Generator::new(141);
+        let build = gen.build_start("a<b>&\"c\"-1.0");
+        gen.log_line(build.id, "\x1b[1m<script>\x1b[0m & 'quotes'");
+        gen.stop(build.id);
+
+        let xml = junit(&gen);
+        assert!(xml.contains(r#"<testcase name="a&lt;b&gt;&amp;&quot;c&quot;-1.0""#));
+        assert!(xml.contains("<system-out>&lt;script&gt; &amp; &apos;quotes&apos;</system-out>"));
+        assert!(!xml.contains('\x1b'));
+    }
+
+    #[test]
+    fn counts() {
+        let mut gen = Generator::new(142);
+        let hello = gen.build_start("hello-2.12.1");
+        gen.stop(hello.id);
+        let failed = gen.build_start("bash-5.2p37");
+        gen.log_line(failed.id, "make: *** [Makefile:42] Error 2");
+        gen.stop(failed.id);
+        gen.build_failed(&failed, 2, &["make: *** [Makefile:42] Error 2"]);
+        let crates = gen.build_start("serde-1_0_218-472e28b9f131b02c");
+        gen.cargo_start(crates.id, "serde", "(lib)");
+        gen.cargo_rustc_exit(crates.id, "serde", "(lib)", 0, vec![]);
+        gen.cargo_start(crates.id, "serde", "(test)");
+        gen.cargo_rustc_exit(
+            crates.id,
+            "serde",
+            "(test)",
+            1,
+            vec![json!({ "rendered": "error[E0308]: mismatched types" })],
+        );
+        gen.cargo_start(crates.id, "serde", "(bin)");
+        // serde's build never stops
+
+        let xml = junit(&gen);
+        assert!(xml.contains(r#"<testsuites name="logone" tests="6" failures="2" errors="2""#));
+        assert!(xml.contains(r#"<testsuite name="nix" tests="3" failures="1" errors="1""#));
+        assert!(xml.contains(r#"<testsuite name="cargo" tests="3" failures="1" errors="1""#));
+        assert!(xml.contains(
+            r#"<failure message="builder failed with exit code 2">make: *** [Makefile:42] Error 2</failure>"#
+        ));
+        assert!(xml.contains(
+            r#"<failure message="serde (test) failed with exit code 1">error[E0308]: mismatched types</failure>"#
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+pub mod html;
+pub mod junit;
+pub mod otlp;
+pub mod prometheus;
+pub mod sarif;
+pub mod summary;
+
+use crate::logone::{derivation_name, Id, LogOne, LogStatus};
+use crate::sinks::cargo_logs::{CargoSession, ExitStatus};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How a build or `@cargo` session ended.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// still running when the input ended
+    Unfinished,
+}
+
+impl Outcome {
+    /// `success`, `failure` or `unfinished`, as shown in the reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Unfinished => "unfinished",
+        }
+    }
+}
+
+/// A derivation nix built, put together from what `LogOne` tracks.
+#[derive(Debug, Clone)]
+pub struct BuildReport {
+    pub id: Id,
+    pub drv: String,
+    pub name: String,
+    pub started: DateTime<Utc>,
+    pub duration: Option<Duration>,
+    pub outcome: Outcome,
+    /// set for failed builds, as far as nix reported it
+    pub exit_status: Option<ExitStatus>,
+    /// empty unless the logs were kept with `LogOne::with_retained_logs`
+    pub log: Vec<String>,
+}
+
+/// A `@cargo` session, finished or not.
+#[derive(Debug, Clone)]
+pub struct SessionReport {
+    pub id: Id,
+    pub session: CargoSession,
+    pub outcome: Outcome,
+}
+
+impl SessionReport {
+    pub fn duration(&self) -> Option<Duration> {
+        self.session
+            .finished
+            .map(|finished| finished - self.session.started)
+    }
+}
+
+/// Every build activity (type 105) in the order they started.
+pub fn builds(logone: &LogOne) -> Vec<BuildReport> {
+    let mut builds: Vec<(usize, BuildReport)> = logone
+        .timings
+        .iter()
+        .filter_map(|(&id, timing)| {
+            let drv = logone.id_to_drv.get(&id)?;
+            let exit_status = logone.build_failures.get(&id).copied();
+            let failed = exit_status.is_some()
+                || logone.nix_log_buffers_state.get(&id) == Some(&LogStatus::FinishedWithError);
+            let outcome = if failed {
+                Outcome::Failure
+            } else if timing.stopped.is_some() {
+                Outcome::Success
+            } else {
+                Outcome::Unfinished
+            };
+            let report = BuildReport {
+                id,
+                drv: drv.clone(),
+                name: derivation_name(drv),
+                started: timing.started,
+                duration: timing.duration(),
+                outcome,
+                exit_status: exit_status.or(failed.then_some(ExitStatus::Unknown)),
+                log: logone.build_logs.get(&id).cloned().unwrap_or_default(),
+            };
+            Some((timing.started_line, report))
+        })
+        .collect();
+    builds.sort_by_key(|(line, _)| *line);
+    builds.into_iter().map(|(_, report)| report).collect()
+}
+
+/// Every `@cargo` session: the ended ones in the order they ended, then the unfinished ones.
+pub fn sessions(logone: &LogOne) -> Vec<SessionReport> {
+    let mut sessions: Vec<SessionReport> = logone
+        .finished_sessions
+        .iter()
+        .map(|(id, session)| SessionReport {
+            id: *id,
+            session: session.clone(),
+            outcome: match session.exit_status {
+                Some(status) if status.success() => Outcome::Success,
+                _ => Outcome::Failure,
+            },
+        })
+        .collect();
+    let mut unfinished: Vec<SessionReport> = logone
+        .cargo_sessions
+        .iter()
+        .filter(|(id, _)| {
+            matches!(
+                logone.cargo_log_buffers_state.get(id),
+                Some(LogStatus::Started | LogStatus::Stopped)
+            )
+        })
+        .map(|(&id, session)| SessionReport {
+            id,
+            session: session.clone(),
+            outcome: Outcome::Unfinished,
+        })
+        .collect();
+    unfinished.sort_by_key(|report| report.session.started);
+    sessions.extend(unfinished);
+    sessions
+}
+
+/// The last `count` lines, like nix shows them for failed builds.
+pub fn tail(lines: &[String], count: usize) -> &[String] {
+    &lines[lines.len().saturating_sub(count)..]
+}
+
+pub(crate) fn seconds(duration: Option<Duration>) -> f64 {
+    duration
+        .and_then(|duration| duration.to_std().ok())
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or(0.0)
+}
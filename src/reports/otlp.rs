@@ -0,0 +1,558 @@
+use crate::logone::{derivation_name, ActivitySpan, Id, LogOne};
+use crate::sinks::cargo_logs::ExitStatus;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// span status codes of OTLP
+const STATUS_UNSET: u64 = 0;
+const STATUS_ERROR: u64 = 2;
+
+/// The trace the spans belong to.
+#[derive(Debug, Clone)]
+pub struct TraceContext {
+    /// 32 hex digits
+    pub trace_id: String,
+    /// 16 hex digits, the span the root span of logone is a child of
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    /// The trace of `TRACEPARENT` (W3C trace context, `00-<trace id>-<span id>-<flags>`)
+    /// as CI systems with tracing set it, otherwise a new trace.
+    pub fn from_env() -> TraceContext {
+        std::env::var("TRACEPARENT")
+            .ok()
+            .and_then(|traceparent| TraceContext::parse(&traceparent))
+            .unwrap_or_else(|| TraceContext {
+                trace_id: format!("{}{}", random_hex(), random_hex()),
+                parent_span_id: None,
+            })
+    }
+
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        let is_hex = |s: &str, len: usize| {
+            s.len() == len
+                && s.chars().all(|c| c.is_ascii_hexdigit())
+                && s.chars().any(|c| c != '0')
+        };
+        match parts.as_slice() {
+            [_, trace_id, span_id, _] if is_hex(trace_id, 32) && is_hex(span_id, 16) => {
+                Some(TraceContext {
+                    trace_id: trace_id.to_lowercase(),
+                    parent_span_id: Some(span_id.to_lowercase()),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// 16 hex digits, RandomState is seeded randomly per process
+fn random_hex() -> String {
+    let hash = RandomState::new().hash_one(Utc::now().timestamp_nanos_opt());
+    format!("{:016x}", hash)
+}
+
+// activity ids are unique within a nix process, which is one trace
+fn span_id(id: Id) -> String {
+    format!("{:016x}", id)
+}
+
+fn nanos(time: DateTime<Utc>) -> String {
+    time.timestamp_nanos_opt().unwrap_or(0).to_string()
+}
+
+fn string(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn bool_attribute(key: &str, value: bool) -> Value {
+    json!({ "key": key, "value": { "boolValue": value } })
+}
+
+// ActivityType of nix's logging.hh
+fn activity_type_name(activity_type: Option<u64>) -> &'static str {
+    match activity_type {
+        Some(100) => "copy-path",
+        Some(101) => "file-transfer",
+        Some(102) => "realise",
+        Some(103) => "copy-paths",
+        Some(104) => "builds",
+        Some(105) => "build",
+        Some(106) => "optimise-store",
+        Some(107) => "verify-paths",
+        Some(108) => "substitute",
+        Some(109) => "query-path-info",
+        Some(110) => "post-build-hook",
+        Some(111) => "build-waiting",
+        Some(112) => "fetch-tree",
+        _ => "unknown",
+    }
+}
+
+fn field(span: &ActivitySpan, index: usize) -> Option<&str> {
+    span.fields
+        .get(index)
+        .and_then(|field| field.as_str())
+        .filter(|field| !field.is_empty())
+}
+
+fn activity_span(
+    id: Id,
+    span: &ActivitySpan,
+    root: &str,
+    logone: &LogOne,
+    now: DateTime<Utc>,
+) -> Value {
+    let activity_type = span.activity.activity_type;
+    let mut attributes = vec![
+        int("nix.activity.id", id as i64),
+        string("nix.activity.type", activity_type_name(activity_type)),
+    ];
+    let mut status = json!({ "code": STATUS_UNSET });
+    let name = match activity_type {
+        // fields [drv, machine, round, nrRounds]
+        Some(105) => {
+            let drv = logone.id_to_drv.get(&id).map(|drv| drv.as_str());
+            if let Some(drv) = drv {
+                attributes.push(string("nix.drv", drv));
+            }
+            if let Some(machine) = field(span, 1) {
+                attributes.push(string("nix.machine", machine));
+            }
+            if let Some((_, phase)) = span.phases.last() {
+                attributes.push(string("nix.phase", phase));
+            }
+            if let Some(exit_status) = logone.build_failures.get(&id) {
+                match exit_status {
+                    ExitStatus::Code(code) => attributes.push(int("nix.exit_code", *code)),
+                    ExitStatus::Signal(signal) => attributes.push(int("nix.signal", *signal)),
+                    ExitStatus::Unknown => {}
+                }
+                status = json!({
+                    "code": STATUS_ERROR,
+                    "message": format!("builder failed with {}", exit_status),
+                });
+            }
+            format!(
+                "build {}",
+                derivation_name(drv.unwrap_or(&span.activity.text))
+            )
+        }
+        // fields [store path, substituter]
+        Some(108) => {
+            if let Some(path) = field(span, 0) {
+                attributes.push(string("nix.store_path", path));
+            }
+            if let Some(substituter) = field(span, 1) {
+                attributes.push(string("nix.substituter", substituter));
+            }
+            format!(
+                "substitute {}",
+                derivation_name(field(span, 0).unwrap_or(""))
+            )
+        }
+        // fields [url]
+        Some(101) => {
+            if let Some(url) = field(span, 0) {
+                attributes.push(string("url.full", url));
+            }
+            if let Some(bytes) = logone.substituted_bytes.get(&id) {
+                attributes.push(int("nix.bytes", *bytes as i64));
+            }
+            span.activity.text.clone()
+        }
+        _ if !span.activity.text.is_empty() => span.activity.text.clone(),
+        _ => activity_type_name(activity_type).to_string(),
+    };
+    if span.stopped.is_none() {
+        attributes.push(bool_attribute("nix.unfinished", true));
+        status = json!({ "code": STATUS_ERROR, "message": "did not finish" });
+    }
+    let parent = match span.activity.parent {
+        Some(parent) if logone.spans.contains_key(&parent) => span_id(parent),
+        _ => root.to_string(),
+    };
+    let events: Vec<Value> = span
+        .phases
+        .iter()
+        .map(|(time, phase)| {
+            json!({
+                "timeUnixNano": nanos(*time),
+                "name": phase,
+                "attributes": [string("nix.phase", phase)],
+            })
+        })
+        .collect();
+    json!({
+        "spanId": span_id(id),
+        "parentSpanId": parent,
+        "name": name,
+        "kind": 1,
+        "startTimeUnixNano": nanos(span.started),
+        "endTimeUnixNano": nanos(span.stopped.unwrap_or(now)),
+        "attributes": attributes,
+        "events": events,
+        "status": status,
+    })
+}
+
+// the @cargo sessions as children of their build
+fn session_spans(logone: &LogOne, now: DateTime<Utc>) -> Vec<Value> {
+    logone
+        .finished_sessions
+        .iter()
+        .enumerate()
+        .filter(|(_, (id, _))| logone.spans.contains_key(id))
+        .map(|(index, (id, session))| {
+            let mut attributes = vec![
+                string("rust.crate.name", &session.crate_name),
+                string("rust.crate.target", &session.crate_type),
+            ];
+            if let Some(version) = &session.crate_version {
+                attributes.push(string("rust.crate.version", version));
+            }
+            let mut status = json!({ "code": STATUS_UNSET });
+            match session.exit_status {
+                Some(ExitStatus::Code(code)) => attributes.push(int("rust.exit_code", code)),
+                Some(ExitStatus::Signal(signal)) => attributes.push(int("rust.signal", signal)),
+                _ => {}
+            }
+            if !session.exit_status.is_some_and(|status| status.success()) {
+                status = json!({ "code": STATUS_ERROR, "message": "compilation failed" });
+            }
+            json!({
+                "spanId": format!("{:016x}", RandomState::new().hash_one((id, index))),
+                "parentSpanId": span_id(*id),
+                "name": format!("rustc {}", session.target_name()),
+                "kind": 1,
+                "startTimeUnixNano": nanos(session.started),
+                "endTimeUnixNano": nanos(session.finished.unwrap_or(now)),
+                "attributes": attributes,
+                "status": status,
+            })
+        })
+        .collect()
+}
+
+/// The activities recorded with `LogOne::with_spans` as an OTLP/JSON
+/// `ExportTraceServiceRequest`: a root span for the whole run, the activities below
+/// it following their `parent` ids and the `@cargo` sessions below their build.
+pub fn otlp_json(logone: &LogOne, context: &TraceContext) -> Value {
+    let now = Utc::now();
+    let root = random_hex();
+    let started = logone
+        .spans
+        .values()
+        .map(|span| span.started)
+        .min()
+        .unwrap_or(now);
+
+    let mut ids: Vec<&Id> = logone.spans.keys().collect();
+    ids.sort_by_key(|id| logone.spans[id].started);
+    let mut spans: Vec<Value> = ids
+        .into_iter()
+        .map(|id| activity_span(*id, &logone.spans[id], &root, logone, now))
+        .collect();
+    spans.extend(session_spans(logone, now));
+
+    let (done, expected, _, failed) = logone.stats().unwrap_or_default();
+    let mut root_span = json!({
+        "spanId": root,
+        "name": "nix build",
+        "kind": 1,
+        "startTimeUnixNano": nanos(started),
+        "endTimeUnixNano": nanos(now),
+        "attributes": [
+            int("nix.builds.done", done as i64),
+            int("nix.builds.expected", expected as i64),
+            int("nix.builds.failed", failed as i64),
+        ],
+        "status": { "code": if failed > 0 { STATUS_ERROR } else { STATUS_UNSET } },
+    });
+    if let Some(parent) = &context.parent_span_id {
+        root_span["parentSpanId"] = json!(parent);
+    }
+    spans.insert(0, root_span);
+    for span in spans.iter_mut() {
+        span["traceId"] = json!(context.trace_id);
+    }
+
+    let service = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "logone".to_string());
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [string("service.name", &service)] },
+            "scopeSpans": [{
+                "scope": { "name": "logone", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Writes the spans as a line of OTLP/JSON, the format of the collector's
+/// `otlpjsonfile` receiver.
+pub fn write_otlp(logone: &LogOne, context: &TraceContext, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{}", otlp_json(logone, context))
+}
+
+/// An OTLP/HTTP receiver, e.g. `http://localhost:4318`, where `/v1/traces` is added
+/// when the URL has no path and port 4318 when it has no port. Only plain HTTP is
+/// supported, for a collector on the same machine or network.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Endpoint {
+    pub url: String,
+    /// host and port, as given in the URL
+    authority: String,
+    address: String,
+    path: String,
+}
+
+impl Endpoint {
+    pub fn parse(url: &str) -> io::Result<Endpoint> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only http:// OTLP endpoints are supported, e.g. http://localhost:4318",
+            )
+        })?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the OTLP endpoint has no host",
+            ));
+        }
+        let path = if path.is_empty() || path == "/" {
+            "/v1/traces"
+        } else {
+            path
+        };
+        // IPv6 addresses are in brackets, the port follows them: http://[::1]:4318
+        let host = match authority.strip_prefix('[') {
+            Some(address) => match address.find(']') {
+                Some(end) => &authority[..end + 2],
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the IPv6 address of the OTLP endpoint has no closing ']'",
+                    ))
+                }
+            },
+            None => authority.split(':').next().unwrap_or(authority),
+        };
+        let address = if host.len() < authority.len() {
+            authority.to_string()
+        } else {
+            format!("{}:4318", authority)
+        };
+        Ok(Endpoint {
+            url: url.to_string(),
+            authority: authority.to_string(),
+            address,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+/// Sends the spans to an OTLP/HTTP receiver, see `Endpoint`.
+pub fn export_http(logone: &LogOne, context: &TraceContext, endpoint: &Endpoint) -> io::Result<()> {
+    let timeout = Duration::from_secs(10);
+    let socket = endpoint.address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("can't resolve {}", endpoint.address),
+        )
+    })?;
+    let mut stream = TcpStream::connect_timeout(&socket, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let body = otlp_json(logone, context).to_string();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        endpoint.authority,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    let status = status_line.split_whitespace().nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} answered '{}'",
+            endpoint,
+            status_line.trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::Generator;
+    use crate::logone::LogLevel;
+    use crate::parser::parse_nix_line;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // a collector answering one request, which it returns as head and body
+    fn collector(listener: TcpListener) -> (String, String) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            head.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    fn nanos_of(span: &Value, key: &str) -> u64 {
+        span[key].as_str().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn export() {
+        let mut gen = Generator::new(151);
+        let lib = gen.build_start("memchr-2_7_4-3cee6db17bbe0dde");
+        let broken = gen.build_start("cargo-credential-0_4_8-a10e20e8704a5f47");
+        gen.phase(lib.id, "buildPhase");
+        gen.cargo_start(lib.id, "memchr", "(lib)");
+        gen.cargo_start(broken.id, "cargo-credential", "(lib)");
+        gen.cargo_rustc_exit(lib.id, "memchr", "(lib)", 0, vec![]);
+        gen.cargo_start(lib.id, "memchr", "(test)");
+        gen.cargo_rustc_exit(lib.id, "memchr", "(test)", 0, vec![]);
+        gen.cargo_rustc_exit(broken.id, "cargo-credential", "(lib)", 1, vec![]);
+        gen.stop(lib.id);
+        gen.stop(broken.id);
+        let mut logone = LogOne::new(false, LogLevel::Cargo).with_spans(true);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint =
+            Endpoint::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        let collector = thread::spawn(move || collector(listener));
+        export_http(&logone, &context, &endpoint).unwrap();
+        let (head, body) = collector.join().unwrap();
+
+        assert!(head.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        let request: Value = serde_json::from_str(&body).unwrap();
+        let spans = request["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap();
+        let by_id: HashMap<&str, &Value> = spans
+            .iter()
+            .map(|span| (span["spanId"].as_str().unwrap(), span))
+            .collect();
+        assert_eq!(by_id.len(), spans.len());
+
+        let root = &spans[0];
+        assert_eq!(root["name"], "nix build");
+        assert_eq!(root["parentSpanId"], "00f067aa0ba902b7");
+        for span in &spans[1..] {
+            assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+            let parent = by_id[span["parentSpanId"].as_str().unwrap()];
+            assert!(nanos_of(span, "startTimeUnixNano") <= nanos_of(span, "endTimeUnixNano"));
+            assert!(nanos_of(parent, "startTimeUnixNano") <= nanos_of(span, "startTimeUnixNano"));
+        }
+
+        // the sessions are below their build, the builds below the root
+        let children = |name: &str| -> Vec<String> {
+            let id = spans.iter().find(|span| span["name"] == name).unwrap()["spanId"].clone();
+            spans
+                .iter()
+                .filter(|span| span["parentSpanId"] == id)
+                .map(|span| span["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            children("build memchr-2_7_4-3cee6db17bbe0dde"),
+            vec!["rustc memchr (lib)", "rustc memchr (test)"]
+        );
+        assert_eq!(
+            children("build cargo-credential-0_4_8-a10e20e8704a5f47"),
+            vec!["rustc cargo-credential (lib)"]
+        );
+        assert!(children("nix build").contains(&"build memchr-2_7_4-3cee6db17bbe0dde".to_string()));
+    }
+
+    #[test]
+    fn endpoint() {
+        let endpoint = Endpoint::parse("http://localhost").unwrap();
+        assert_eq!(endpoint.address, "localhost:4318");
+        assert_eq!(endpoint.path, "/v1/traces");
+        let endpoint = Endpoint::parse("http://collector:4000/otlp/v1/traces").unwrap();
+        assert_eq!(endpoint.address, "collector:4000");
+        assert_eq!(endpoint.path, "/otlp/v1/traces");
+
+        let error = Endpoint::parse("https://localhost:4318").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            error.to_string(),
+            "only http:// OTLP endpoints are supported, e.g. http://localhost:4318"
+        );
+        assert!(Endpoint::parse("http:///v1/traces").is_err());
+    }
+
+    #[test]
+    fn ipv6_endpoint() {
+        let endpoint = Endpoint::parse("http://[::1]/v1/traces").unwrap();
+        assert_eq!(endpoint.address, "[::1]:4318");
+        assert_eq!(endpoint.authority, "[::1]");
+        assert_eq!(endpoint.path, "/v1/traces");
+        let endpoint = Endpoint::parse("http://[fd00::2]:4000").unwrap();
+        assert_eq!(endpoint.address, "[fd00::2]:4000");
+        assert_eq!(endpoint.path, "/v1/traces");
+        assert!(Endpoint::parse("http://[::1/v1/traces").is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use super::{builds, seconds, Outcome};
+use crate::logone::LogOne;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// upper bounds of the duration histogram in seconds, builds take from seconds to hours
+const BUCKETS: [f64; 10] = [
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut impl Write, name: &str, kind: &str, help: &str) -> io::Result<()> {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+/// rustc warnings and errors per crate, each diagnostic counted once per crate.
+fn diagnostics_by_crate(logone: &LogOne) -> BTreeMap<String, (usize, usize)> {
+    // crates without diagnostics are reported with 0
+    let mut counts: BTreeMap<String, (usize, usize)> = logone
+        .finished_sessions
+        .iter()
+        .map(|(_, session)| (session.crate_name.clone(), (0, 0)))
+        .collect();
+    for (session, diagnostic) in logone.unique_diagnostics() {
+        let entry = counts.entry(session.crate_name.clone()).or_default();
+        if diagnostic.is_warning() {
+            entry.0 += 1;
+        } else if diagnostic.level.starts_with("error") {
+            entry.1 += 1;
+        }
+    }
+    counts
+}
+
+/// Writes the metrics of the build in the Prometheus text format, for the
+/// textfile collector of the node exporter.
+pub fn write_metrics(logone: &LogOne, out: &mut impl Write) -> io::Result<()> {
+    let (done, expected, running, failed) = logone.stats().unwrap_or_default();
+    for (name, value, help) in [
+        (
+            "done",
+            done,
+            "Derivations built or substituted, as reported by nix.",
+        ),
+        (
+            "expected",
+            expected,
+            "Derivations nix is going to build or substitute.",
+        ),
+        ("running", running, "Derivations being built."),
+        ("failed", failed, "Derivations which failed to build."),
+    ] {
+        let name = format!("logone_builds_{}", name);
+        header(out, &name, "gauge", help)?;
+        writeln!(out, "{} {}", name, value)?;
+    }
+
+    let builds = builds(logone);
+    let finished: Vec<_> = builds
+        .iter()
+        .filter(|build| build.outcome != Outcome::Unfinished && build.duration.is_some())
+        .collect();
+    header(
+        out,
+        "logone_build_duration_seconds",
+        "histogram",
+        "Duration of the derivation builds.",
+    )?;
+    for outcome in [Outcome::Success, Outcome::Failure] {
+        let label = outcome.name();
+        let durations: Vec<f64> = finished
+            .iter()
+            .filter(|build| build.outcome == outcome)
+            .map(|build| seconds(build.duration))
+            .collect();
+        for bucket in BUCKETS {
+            let count = durations.iter().filter(|&&d| d <= bucket).count();
+            writeln!(
+                out,
+                "logone_build_duration_seconds_bucket{{outcome=\"{}\",le=\"{}\"}} {}",
+                label, bucket, count
+            )?;
+        }
+        writeln!(
+            out,
+            "logone_build_duration_seconds_bucket{{outcome=\"{}\",le=\"+Inf\"}} {}",
+            label,
+            durations.len()
+        )?;
+        writeln!(
+            out,
+            "logone_build_duration_seconds_sum{{outcome=\"{}\"}} {}",
+            label,
+            durations.iter().fold(0.0, |sum, d| sum + d)
+        )?;
+        writeln!(
+            out,
+            "logone_build_duration_seconds_count{{outcome=\"{}\"}} {}",
+            label,
+            durations.len()
+        )?;
+    }
+
+    header(
+        out,
+        "logone_derivation_duration_seconds",
+        "gauge",
+        "Duration of the build of each derivation.",
+    )?;
+    // the name alone is not unique, e.g. for the same package built for two systems
+    for build in &finished {
+        writeln!(
+            out,
+            "logone_derivation_duration_seconds{{name=\"{}\",drv=\"{}\",outcome=\"{}\"}} {}",
+            escape_label(&build.name),
+            escape_label(&build.drv),
+            build.outcome.name(),
+            seconds(build.duration)
+        )?;
+    }
+
+    header(
+        out,
+        "logone_substituted_bytes",
+        "gauge",
+        "Bytes downloaded from binary caches.",
+    )?;
+    writeln!(
+        out,
+        "logone_substituted_bytes {}",
+        logone.total_substituted_bytes()
+    )?;
+
+    let diagnostics = diagnostics_by_crate(logone);
+    header(
+        out,
+        "logone_rustc_warnings",
+        "gauge",
+        "rustc warnings per crate.",
+    )?;
+    for (crate_name, (warnings, _)) in &diagnostics {
+        writeln!(
+            out,
+            "logone_rustc_warnings{{crate=\"{}\"}} {}",
+            escape_label(crate_name),
+            warnings
+        )?;
+    }
+    header(
+        out,
+        "logone_rustc_errors",
+        "gauge",
+        "rustc errors per crate.",
+    )?;
+    for (crate_name, (_, errors)) in &diagnostics {
+        writeln!(
+            out,
+            "logone_rustc_errors{{crate=\"{}\"}} {}",
+            escape_label(crate_name),
+            errors
+        )?;
+    }
+
+    header(
+        out,
+        "logone_last_update_timestamp_seconds",
+        "gauge",
+        "When the metrics were written.",
+    )?;
+    writeln!(
+        out,
+        "logone_last_update_timestamp_seconds {}",
+        Utc::now().timestamp()
+    )
+}
+
+/// Writes the metrics to `path` through a temporary file, so the collector never
+/// reads a half written file.
+pub fn write_metrics_file(logone: &LogOne, path: &Path) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    write_metrics(logone, &mut out)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{rustc_diagnostic, Generator};
+    use crate::logone::LogLevel;
+    use crate::parser::parse_nix_line;
+
+    fn metrics(gen: &Generator) -> String {
+        let mut logone = LogOne::new(false, LogLevel::Cargo);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        let mut out = Vec::new();
+        write_metrics(&logone, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn durations() {
+        let mut gen = Generator::new(150);
+        let first = gen.build_start("hello-2.12.1");
+        let second = gen.build_start("hello-2.12.1");
+        gen.stop(first.id);
+        gen.stop(second.id);
+        gen.build_failed(&second, 2, &[]);
+        gen.build_start("bash-5.2p37");
+        let crates = gen.build_start("my-crate-0_1_0");
+        let warning = rustc_diagnostic("warning", Some("unused"), "unused", "src/lib.rs", 3, 5);
+        let error = rustc_diagnostic(
+            "error",
+            Some("E0308"),
+            "mismatched types",
+            "src/lib.rs",
+            7,
+            9,
+        );
+        gen.cargo_start(crates.id, "my-crate", "(lib)");
+        gen.cargo_rustc_exit(
+            crates.id,
+            "my-crate",
+            "(lib)",
+            1,
+            vec![warning.clone(), error],
+        );
+        gen.cargo_start(crates.id, "my-crate", "(test)");
+        gen.cargo_rustc_exit(crates.id, "my-crate", "(test)", 0, vec![warning]);
+        gen.cargo_start(crates.id, "libc", "(lib)");
+        gen.cargo_rustc_exit(crates.id, "libc", "(lib)", 0, vec![]);
+        gen.stop(crates.id);
+
+        let metrics = metrics(&gen);
+        let lines: Vec<&str> = metrics.lines().collect();
+        // the builds take far less than a second, the unfinished one is left out
+        assert!(
+            lines.contains(&r#"logone_build_duration_seconds_bucket{outcome="success",le="1"} 2"#)
+        );
+        assert!(lines
+            .contains(&r#"logone_build_duration_seconds_bucket{outcome="success",le="3600"} 2"#));
+        assert!(lines
+            .contains(&r#"logone_build_duration_seconds_bucket{outcome="success",le="+Inf"} 2"#));
+        assert!(lines.contains(&r#"logone_build_duration_seconds_count{outcome="success"} 2"#));
+        assert!(lines
+            .contains(&r#"logone_build_duration_seconds_bucket{outcome="failure",le="+Inf"} 1"#));
+        assert!(lines.contains(&r#"logone_build_duration_seconds_count{outcome="failure"} 1"#));
+
+        // both hello builds, told apart by their derivation
+        let derivations: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("logone_derivation_duration_seconds{"))
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(derivations.len(), 3);
+        assert!(derivations[0].starts_with(r#"name="hello-2.12.1",drv="/nix/store/"#));
+        assert!(derivations[1].starts_with(r#"name="hello-2.12.1",drv="/nix/store/"#));
+        assert_ne!(derivations[0], derivations[1]);
+        assert!(derivations
+            .iter()
+            .any(|labels| labels.ends_with(r#"outcome="failure"}"#)));
+
+        // the warning of the tests was counted for the library already
+        assert!(lines.contains(&r#"logone_rustc_warnings{crate="my-crate"} 1"#));
+        assert!(lines.contains(&r#"logone_rustc_errors{crate="my-crate"} 1"#));
+        assert!(lines.contains(&r#"logone_rustc_warnings{crate="libc"} 0"#));
+        assert!(lines.contains(&r#"logone_rustc_errors{crate="libc"} 0"#));
+    }
+}
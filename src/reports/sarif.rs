@@ -0,0 +1,237 @@
+use crate::ansi::strip_ansi;
+use crate::logone::LogOne;
+use crate::rustc::{workspace_path, RustcDiagnostic};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+
+/// Rule id of diagnostics rustc has no code for, e.g. syntax errors.
+const NO_CODE: &str = "rustc";
+
+// "error: internal compiler error" is an error, "failure-note" and "help" are notes
+fn sarif_level(level: &str) -> &'static str {
+    match level {
+        level if level.starts_with("error") => "error",
+        "warning" => "warning",
+        "note" | "help" | "failure-note" => "note",
+        _ => "none",
+    }
+}
+
+fn help_uri(code: &str) -> Option<String> {
+    if let Some(lint) = code.strip_prefix("clippy::") {
+        Some(format!(
+            "https://rust-lang.github.io/rust-clippy/master/index.html#{}",
+            lint
+        ))
+    } else if code.len() > 1
+        && code.starts_with('E')
+        && code[1..].chars().all(|c| c.is_ascii_digit())
+    {
+        Some(format!(
+            "https://doc.rust-lang.org/error_codes/{}.html",
+            code
+        ))
+    } else {
+        None
+    }
+}
+
+fn location(diagnostic: &RustcDiagnostic) -> Option<Value> {
+    let span = diagnostic.primary_span()?;
+    Some(json!({
+        "physicalLocation": {
+            "artifactLocation": {
+                "uri": workspace_path(&span.file_name),
+                "uriBaseId": "%SRCROOT%",
+            },
+            "region": {
+                "startLine": span.line_start,
+                "startColumn": span.column_start,
+                "endLine": span.line_end,
+                "endColumn": span.column_end,
+            },
+        },
+    }))
+}
+
+/// A SARIF 2.1.0 log of the rustc diagnostics of all `@cargo` sessions.
+///
+/// Like the terminal output, diagnostics are reported once per crate and warnings of
+/// crates outside of `LogOne::with_local_crates` are left out. The sessions are only
+/// collected at the `cargo` and `hybrid` log levels.
+pub fn sarif(logone: &LogOne) -> Value {
+    let mut rules: Vec<Value> = Vec::new();
+    let mut rule_ids: Vec<String> = Vec::new();
+    let mut results: Vec<Value> = Vec::new();
+
+    for (session, diagnostic) in logone.unique_diagnostics() {
+        if !logone.is_local_crate(&session.crate_name) && !diagnostic.level.starts_with("error") {
+            continue;
+        }
+
+        let rule_id = diagnostic.code().unwrap_or(NO_CODE).to_string();
+        let rule_index = match rule_ids.iter().position(|id| *id == rule_id) {
+            Some(index) => index,
+            None => {
+                let mut rule = json!({
+                    "id": rule_id,
+                    "shortDescription": { "text": diagnostic.message },
+                });
+                if let Some(uri) = help_uri(&rule_id) {
+                    rule["helpUri"] = json!(uri);
+                }
+                rules.push(rule);
+                rule_ids.push(rule_id.clone());
+                rule_ids.len() - 1
+            }
+        };
+
+        let mut result = json!({
+            "ruleId": rule_id,
+            "ruleIndex": rule_index,
+            "level": sarif_level(&diagnostic.level),
+            "message": { "text": diagnostic.message },
+            "locations": location(diagnostic).into_iter().collect::<Vec<Value>>(),
+            "properties": { "crate": session.crate_name, "target": session.target_name() },
+        });
+        if let Some(rendered) = &diagnostic.rendered {
+            result["message"]["markdown"] =
+                json!(format!("```\n{}\n```", strip_ansi(rendered).trim_end()));
+        }
+        results.push(result);
+    }
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rustc",
+                    "informationUri": "https://doc.rust-lang.org/rustc/",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+pub fn write_sarif(logone: &LogOne, out: &mut impl Write) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, &sarif(logone))?;
+    writeln!(out)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{rustc_diagnostic, Generator};
+    use crate::logone::LogLevel;
+    use crate::parser::parse_nix_line;
+
+    #[test]
+    fn document() {
+        let mut gen = Generator::new(143);
+        let build = gen.build_start("regex-1_11_1-5e0e2a1d3c4b5a69");
+        gen.phase(build.id, "buildPhase");
+        let mut warning = rustc_diagnostic(
+            "warning",
+            Some("unused_imports"),
+            "unused import: `std::fmt`",
+            "/build/regex-1.11.1/src/lib.rs",
+            3,
+            5,
+        );
+        warning["rendered"] = json!("\x1b[33mwarning[unused_imports]\x1b[0m: unused import\n");
+        gen.cargo_start(build.id, "regex", "(lib)");
+        gen.cargo_rustc_exit(build.id, "regex", "(lib)", 0, vec![warning.clone()]);
+        let error = rustc_diagnostic(
+            "error",
+            Some("E0308"),
+            "mismatched types",
+            "/build/regex-1.11.1/tests/lib.rs",
+            7,
+            13,
+        );
+        gen.cargo_start(build.id, "regex", "(test)");
+        gen.cargo_rustc_exit(build.id, "regex", "(test)", 1, vec![warning, error]);
+        gen.stop(build.id);
+
+        let mut logone = LogOne::new(false, LogLevel::Cargo);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        let sarif = sarif(&logone);
+        let run = &sarif["runs"][0];
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([
+                { "id": "unused_imports", "shortDescription": { "text": "unused import: `std::fmt`" } },
+                {
+                    "id": "E0308",
+                    "shortDescription": { "text": "mismatched types" },
+                    "helpUri": "https://doc.rust-lang.org/error_codes/E0308.html",
+                },
+            ])
+        );
+
+        // the warning of the tests was reported for the library already
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["level"], "warning");
+        assert_eq!(
+            results[0]["message"]["markdown"],
+            "```\nwarning[unused_imports]: unused import\n```"
+        );
+        assert_eq!(
+            results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "src/lib.rs"
+        );
+        assert_eq!(results[1]["ruleIndex"], 1);
+        assert_eq!(results[1]["level"], "error");
+        assert_eq!(
+            results[1]["properties"],
+            json!({ "crate": "regex", "target": "regex (test)" })
+        );
+        assert_eq!(
+            results[1]["locations"][0]["physicalLocation"]["region"],
+            json!({ "startLine": 7, "startColumn": 13, "endLine": 7, "endColumn": 14 })
+        );
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut gen = Generator::new(144);
+        let build = gen.build_start("libc-0_2_170-00cb1c0a8601ad49");
+        let warning = rustc_diagnostic(
+            "warning",
+            Some("unused_imports"),
+            "unused import",
+            "/build/libc-0.2.170/src/lib.rs",
+            3,
+            5,
+        );
+        let error = rustc_diagnostic(
+            "error",
+            Some("E0308"),
+            "mismatched types",
+            "/build/libc-0.2.170/src/lib.rs",
+            9,
+            1,
+        );
+        gen.cargo_start(build.id, "libc", "(lib)");
+        gen.cargo_rustc_exit(build.id, "libc", "(lib)", 1, vec![warning, error]);
+        gen.stop(build.id);
+
+        let mut logone = LogOne::new(false, LogLevel::Cargo).with_local_crates(["regex"]);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        // errors of dependencies are reported, their warnings are not
+        let results = sarif(&logone)["runs"][0]["results"].clone();
+        assert_eq!(results.as_array().unwrap().len(), 1);
+        assert_eq!(results[0]["ruleId"], "E0308");
+    }
+}
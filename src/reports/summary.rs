@@ -0,0 +1,264 @@
+use super::{builds, seconds, tail, Outcome};
+use crate::ansi::strip_ansi;
+use crate::logone::LogOne;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Lines of the log shown for each failed derivation.
+const FAILURE_LINES: usize = 30;
+/// Number of builds listed as the slowest.
+const SLOWEST: usize = 10;
+
+/// Warnings per crate as shown on the terminal: once per crate and only for the
+/// crates of `LogOne::with_local_crates`.
+pub fn warnings_by_crate(logone: &LogOne) -> BTreeMap<String, usize> {
+    let mut warnings: BTreeMap<String, usize> = BTreeMap::new();
+    for (session, diagnostic) in logone.unique_diagnostics() {
+        if diagnostic.is_warning() && logone.is_local_crate(&session.crate_name) {
+            *warnings.entry(session.crate_name.clone()).or_insert(0) += 1;
+        }
+    }
+    warnings
+}
+
+// keeps table cells in one piece
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+// a code fence the lines can't close: longer than their longest run of backticks
+fn fence(lines: &[String]) -> String {
+    let longest = lines
+        .iter()
+        .flat_map(|line| line.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+// keeps the HTML of the <details> blocks in one piece
+fn html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Writes a Markdown summary, e.g. for `$GITHUB_STEP_SUMMARY`: totals, the failed
+/// derivations with the end of their log, warnings per crate and the slowest builds.
+///
+/// Build logs are only included if they were kept with `LogOne::with_retained_logs`.
+pub fn write_summary(logone: &LogOne, out: &mut impl Write) -> io::Result<()> {
+    let builds = builds(logone);
+    let failed: Vec<_> = builds
+        .iter()
+        .filter(|build| build.outcome == Outcome::Failure)
+        .collect();
+    let unfinished = builds
+        .iter()
+        .filter(|build| build.outcome == Outcome::Unfinished)
+        .count();
+
+    let headline = if failed.is_empty() && unfinished == 0 {
+        "✅ nix build succeeded"
+    } else {
+        "❌ nix build failed"
+    };
+    writeln!(out, "## {}\n", headline)?;
+    writeln!(out, "| done | expected | failed | unfinished |")?;
+    writeln!(out, "|---:|---:|---:|---:|")?;
+    let (done, expected) = match logone.stats() {
+        Some((done, expected, _, _)) => (done, expected),
+        None => (
+            builds
+                .iter()
+                .filter(|build| build.outcome == Outcome::Success)
+                .count() as u64,
+            builds.len() as u64,
+        ),
+    };
+    writeln!(
+        out,
+        "| {} | {} | {} | {} |\n",
+        done,
+        expected,
+        failed.len(),
+        unfinished
+    )?;
+
+    if !failed.is_empty() {
+        writeln!(out, "### Failed derivations\n")?;
+        for build in &failed {
+            let status = build
+                .exit_status
+                .map(|status| format!(" ({})", status))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "<details><summary><code>{}</code>{}</summary>\n",
+                html(&build.name),
+                status
+            )?;
+            // rustc's errors are in the failed @cargo sessions of the derivation
+            let mut log = build.log.clone();
+            for (id, session) in &logone.finished_sessions {
+                if *id == build.id && !session.exit_status.is_some_and(|s| s.success()) {
+                    log.extend(
+                        session
+                            .messages
+                            .iter()
+                            .flat_map(|m| m.lines())
+                            .map(String::from),
+                    );
+                }
+            }
+            let lines: Vec<String> = tail(&log, FAILURE_LINES)
+                .iter()
+                .map(|line| strip_ansi(line).into_owned())
+                .collect();
+            let fence = fence(&lines);
+            writeln!(out, "{}", fence)?;
+            for line in &lines {
+                writeln!(out, "{}", line)?;
+            }
+            writeln!(out, "{}\n\n`nix log {}`\n</details>\n", fence, build.drv)?;
+        }
+    }
+
+    let warnings = warnings_by_crate(logone);
+    if !warnings.is_empty() {
+        writeln!(out, "### Warnings\n")?;
+        writeln!(out, "| crate | warnings |")?;
+        writeln!(out, "|---|---:|")?;
+        for (crate_name, count) in &warnings {
+            writeln!(out, "| {} | {} |", cell(crate_name), count)?;
+        }
+        writeln!(out)?;
+    }
+
+    let mut slowest: Vec<_> = builds
+        .iter()
+        .filter(|build| build.duration.is_some())
+        .collect();
+    slowest.sort_by_key(|build| std::cmp::Reverse(build.duration));
+    if !slowest.is_empty() {
+        writeln!(out, "### Slowest builds\n")?;
+        writeln!(out, "| derivation | duration |")?;
+        writeln!(out, "|---|---:|")?;
+        for build in slowest.iter().take(SLOWEST) {
+            writeln!(
+                out,
+                "| {} | {:.1}s |",
+                cell(&build.name),
+                seconds(build.duration)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{rustc_diagnostic, Generator};
+    use crate::logone::LogLevel;
+    use crate::parser::parse_nix_line;
+    use serde_json::json;
+
+    fn summary(gen: &Generator, logone: LogOne) -> (LogOne, String) {
+        let mut logone = logone.with_retained_logs(true);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
+        }
+        let mut out = Vec::new();
+        write_summary(&logone, &mut out).unwrap();
+        (logone, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn failed_derivation() {
+        let mut gen = Generator::new(147);
+        let hello = gen.build_start("hello-2.12.1");
+        gen.stop(hello.id);
+        let failed = gen.build_start("quote-1.0.38");
+        gen.log_line(failed.id, "\x1b[1mmaking\x1b[0m all");
+        gen.log_line(failed.id, "see ```make``` | ``x``");
+        gen.stop(failed.id);
+        gen.build_failed(&failed, 2, &["see ```make``` | ``x``"]);
+
+        let (_, summary) = summary(&gen, LogOne::new(true, LogLevel::Errors));
+        assert!(summary.starts_with("## ❌ nix build failed\n"));
+        assert!(summary.contains("| done | expected | failed | unfinished |"));
+        assert!(summary.contains("| 1 | 2 | 1 | 0 |"));
+        assert!(summary.contains(
+            "<details><summary><code>quote-1.0.38</code> (exit code 2)</summary>\n\n\
+             ````\nmaking all\nsee ```make``` | ``x``\n````\n\n`nix log /nix/store/"
+        ));
+        assert!(!summary.contains('\x1b'));
+    }
+
+    #[test]
+    fn warnings_and_slowest() {
+        let mut gen = Generator::new(148);
+        let build = gen.build_start("my-crate-0_1_0");
+        let warning = rustc_diagnostic(
+            "warning",
+            Some("unused_imports"),
+            "unused import: `std::fmt`",
+            "src/lib.rs",
+            3,
+            5,
+        );
+        let summary_line = json!({
+            "$message_type": "diagnostic",
+            "message": "1 warning emitted",
+            "level": "warning",
+            "spans": [],
+            "children": [],
+            "rendered": "warning: 1 warning emitted\n",
+        });
+        gen.cargo_start(build.id, "my-crate", "(lib)");
+        gen.cargo_rustc_exit(
+            build.id,
+            "my-crate",
+            "(lib)",
+            0,
+            vec![warning.clone(), summary_line],
+        );
+        // the same warning of the tests is shown once
+        gen.cargo_start(build.id, "my-crate", "(test)");
+        gen.cargo_rustc_exit(build.id, "my-crate", "(test)", 0, vec![warning.clone()]);
+        gen.cargo_start(build.id, "libc", "(lib)");
+        gen.cargo_rustc_exit(build.id, "libc", "(lib)", 0, vec![warning]);
+        gen.stop(build.id);
+
+        let logone = LogOne::new(false, LogLevel::Cargo).with_local_crates(["my-crate"]);
+        let (logone, summary) = summary(&gen, logone);
+        assert_eq!(
+            warnings_by_crate(&logone),
+            BTreeMap::from([("my-crate".to_string(), 1)])
+        );
+        assert!(summary.starts_with("## ✅ nix build succeeded\n"));
+        assert!(summary
+            .contains("### Warnings\n\n| crate | warnings |\n|---|---:|\n| my-crate | 1 |\n"));
+        assert!(summary.contains(
+            "### Slowest builds\n\n| derivation | duration |\n|---|---:|\n| my-crate-0_1_0 | "
+        ));
+    }
+
+    #[test]
+    fn fences() {
+        let lines = |lines: &[&str]| {
+            lines
+                .iter()
+                .map(|line| line.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fence(&lines(&["no backticks"])), "```");
+        assert_eq!(fence(&lines(&["`a` ``b``"])), "```");
+        assert_eq!(fence(&lines(&["```", "`````rust"])), "``````");
+        assert_eq!(html("<a & b>"), "&lt;a &amp; b&gt;");
+        assert_eq!(cell("a | b"), "a \\| b");
+    }
+}
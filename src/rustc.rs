@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A diagnostic from `rustc --error-format=json`, as found in `rustc_messages` of `@cargo` type 2.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RustcDiagnostic {
+    pub message: String,
+    pub code: Option<RustcDiagnosticCode>,
+    /// "error", "warning", "note", "help", "failure-note", "error: internal compiler error"
+    pub level: String,
+    pub spans: Vec<RustcSpan>,
+    pub children: Vec<RustcDiagnostic>,
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RustcDiagnosticCode {
+    pub code: String,
+    pub explanation: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RustcSpan {
+    pub file_name: String,
+    pub byte_start: u64,
+    pub byte_end: u64,
+    pub line_start: u64,
+    pub line_end: u64,
+    pub column_start: u64,
+    pub column_end: u64,
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+impl RustcDiagnostic {
+    /// Parses an entry of `rustc_messages`, `None` for artifacts and other message types.
+    pub fn from_value(value: &Value) -> Option<RustcDiagnostic> {
+        let obj = value.as_object()?;
+        match obj.get("$message_type").and_then(|v| v.as_str()) {
+            Some("diagnostic") | None if obj.contains_key("level") => {
+                serde_json::from_value(value.clone()).ok()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn primary_span(&self) -> Option<&RustcSpan> {
+        self.spans
+            .iter()
+            .find(|span| span.is_primary)
+            .or_else(|| self.spans.first())
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_ref().map(|code| code.code.as_str())
+    }
+
+    pub fn is_warning(&self) -> bool {
+        self.level == "warning"
+    }
+
+    /// rustc's closing "N warnings emitted" / "aborting due to N previous errors"
+    pub fn is_summary(&self) -> bool {
+        self.spans.is_empty()
+            && self.code.is_none()
+            && (self.message.ends_with("emitted") || self.message.starts_with("aborting due to"))
+    }
+
+    /// Identifies a diagnostic by level, code, message and primary span.
+    pub fn dedup_key(&self) -> String {
+        let span = self
+            .primary_span()
+            .map(|span| {
+                format!(
+                    "{}:{}:{}",
+                    span.file_name, span.line_start, span.column_start
+                )
+            })
+            .unwrap_or_default();
+        format!(
+            "{}|{}|{}|{}",
+            self.level,
+            self.code().unwrap_or(""),
+            self.message,
+            span
+        )
+    }
+}
+
+/// A file rustc wrote, `{"$message_type":"artifact","artifact":"...","emit":"link"}`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RustcArtifact {
+    pub artifact: String,
+    /// "link", "metadata", "dep-info", ...
+    pub emit: String,
+}
+
+impl RustcArtifact {
+    pub fn from_value(value: &Value) -> Option<RustcArtifact> {
+        match value.get("$message_type").and_then(|v| v.as_str()) {
+            Some("artifact") => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the path of a source file in the nix sandbox into one relative to the
+/// workspace: `/build/<source root>/src/lib.rs` and `/nix/store/<hash>-source/src/lib.rs`
+/// become `src/lib.rs`, relative paths are kept.
+pub fn workspace_path(file_name: &str) -> String {
+    let rest = file_name
+        .strip_prefix("/build/")
+        .or_else(|| file_name.strip_prefix("/nix/store/"));
+    match rest.and_then(|rest| rest.split_once('/')) {
+        Some((_, path)) => path.to_string(),
+        None => file_name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn warning(message: &str, line_start: u64) -> RustcDiagnostic {
+        RustcDiagnostic::from_value(&json!({
+            "$message_type": "diagnostic",
+            "message": message,
+            "code": { "code": "unused_imports", "explanation": null },
+            "level": "warning",
+            "spans": [
+                { "file_name": "src/main.rs", "line_start": 1, "column_start": 1, "is_primary": false },
+                { "file_name": "src/lib.rs", "line_start": line_start, "column_start": 5, "is_primary": true }
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn dedup_key() {
+        assert_eq!(
+            warning("unused import: `std::fmt`", 3).dedup_key(),
+            "warning|unused_imports|unused import: `std::fmt`|src/lib.rs:3:5"
+        );
+        assert_ne!(
+            warning("unused import: `std::fmt`", 3).dedup_key(),
+            warning("unused import: `std::fmt`", 4).dedup_key()
+        );
+        let summary = RustcDiagnostic {
+            level: "warning".to_string(),
+            message: "1 warning emitted".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(summary.dedup_key(), "warning||1 warning emitted|");
+    }
+
+    #[test]
+    fn is_summary() {
+        let diagnostic = |level: &str, message: &str| RustcDiagnostic {
+            level: level.to_string(),
+            message: message.to_string(),
+            ..Default::default()
+        };
+        assert!(diagnostic("warning", "3 warnings emitted").is_summary());
+        assert!(diagnostic("error", "aborting due to 2 previous errors").is_summary());
+        assert!(!diagnostic("warning", "unused variable: `x`").is_summary());
+        // a diagnostic with a span is never rustc's summary
+        assert!(!warning("2 warnings emitted", 3).is_summary());
+        // artifacts are not diagnostics
+        assert!(RustcDiagnostic::from_value(&json!({
+            "$message_type": "artifact",
+            "artifact": "/nix/store/0aa-memchr/lib/libmemchr.rlib",
+            "emit": "link",
+        }))
+        .is_none());
+    }
+
+    #[test]
+    fn workspace_path() {
+        assert_eq!(
+            super::workspace_path("/build/regex-1.11.1/src/lib.rs"),
+            "src/lib.rs"
+        );
+        assert_eq!(
+            super::workspace_path("/nix/store/0aa-source/crates/cli/src/main.rs"),
+            "crates/cli/src/main.rs"
+        );
+        assert_eq!(super::workspace_path("src/lib.rs"), "src/lib.rs");
+        // no path below the source root
+        assert_eq!(super::workspace_path("/build/lib.rs"), "/build/lib.rs");
+        assert_eq!(super::workspace_path("/usr/lib.rs"), "/usr/lib.rs");
+    }
+}
@@ -1,174 +1,855 @@
-use crate::{logone, logone::LogStatus};
-use anyhow::{anyhow, Result};
+use crate::{
+    build_script::BuildScriptOutput,
+    ci::{self, CiFormat},
+    error::{LogoneError, Result},
+    events::Event,
+    logone,
+    logone::{derivation_name, LogStatus},
+    parser::get_id,
+    rustc::{RustcArtifact, RustcDiagnostic},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use serde_json::{Map, Value};
+use std::fmt;
 
-fn get_target_name(obj: &Map<String, Value>) -> String {
-    let crate_name = obj
-        .get("crate_name")
-        .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
+/// How the process behind a `@cargo` session end terminated.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub enum ExitStatus {
+    /// the code the process exited with, also above 128: rustc and build scripts
+    /// report it as a plain integer, so 130 or 137 are real exit codes
+    Code(i64),
+    /// killed by a signal, reported as negative code
+    Signal(i64),
+    /// the end message had no usable exit code
+    Unknown,
+}
 
-    let crate_type = obj
-        .get("crate_type")
-        .and_then(|v| v.as_str())
-        .unwrap_or("")
-        .to_string();
+impl ExitStatus {
+    pub fn from_value(value: &Value) -> ExitStatus {
+        let code = match value {
+            Value::Number(n) => n.as_i64(),
+            // (.. | tonumber) in jq might have been skipped by the sender
+            Value::String(s) => s.trim().parse::<i64>().ok(),
+            _ => None,
+        };
+        match code {
+            Some(code) if code < 0 => ExitStatus::Signal(-code),
+            Some(code) => ExitStatus::Code(code),
+            None => ExitStatus::Unknown,
+        }
+    }
 
-    if crate_type.is_empty() {
-        crate_name.clone()
-    } else {
-        format!("{} {}", crate_name, crate_type)
+    pub fn success(&self) -> bool {
+        *self == ExitStatus::Code(0)
     }
 }
 
-// echo "@cargo { \"type\":0, \"crate_name\":\"{{{crate_name}}}\", \"crate_type\":\"{{{crate_type}}}\", \"id\":\"{{{fullname}}}\" }"
-pub fn handle_cargo_log_start(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log start"))?;
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "exit code {}", code),
+            ExitStatus::Signal(signal) => write!(f, "signal {}", signal),
+            ExitStatus::Unknown => write!(f, "unknown exit status"),
+        }
+    }
+}
 
-    // Create new log buffer for this id
-    logone.cargo_log_buffers.insert(id, Vec::new());
-    logone
-        .cargo_log_buffers_state
-        .insert(id, LogStatus::Started);
+/// A crate build announced by `@cargo` messages, keyed by the id of the nix
+/// activity it was embedded in.
+///
+/// lifecycle: start (type 0) -> messages (type 1, any number) -> end (type 2 or 3)
+#[derive(Debug, Clone, Serialize)]
+pub struct CargoSession {
+    pub crate_name: String,
+    pub crate_type: String,
+    /// `crate_version` of the type 0 message, or guessed from the derivation name
+    pub crate_version: Option<String>,
+    pub exit_status: Option<ExitStatus>,
+    /// the `/nix/store/...drv` of the nix build activity the session is embedded in
+    pub derivation: Option<String>,
+    /// the store path the artifacts were written to, set by a type 2 end
+    pub out_path: Option<String>,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    /// ended by the stop of its activity, without an end message, see `handle_activity_stop`
+    pub ended_by_stop: bool,
+    /// instructions of a `(build.rs run)`, set by its type 3 end
+    pub build_script: Option<BuildScriptOutput>,
+    /// the JSON diagnostics of rustc, set by a type 2 end
+    pub diagnostics: Vec<RustcDiagnostic>,
+    /// files rustc wrote, set by a type 2 end
+    pub artifacts: Vec<RustcArtifact>,
+    /// everything the session sent, set by its end as the buffer is reused by the next session
+    pub messages: Vec<String>,
+}
 
-    let target_name = get_target_name(&obj);
-    
-    logone.target_add(target_name.clone())?;
+impl CargoSession {
+    pub fn target_name(&self) -> String {
+        if self.crate_type.is_empty() {
+            self.crate_name.clone()
+        } else {
+            format!("{} {}", self.crate_name, self.crate_type)
+        }
+    }
 
-    let msg: String = format!("   \x1b[32mCompiling\x1b[0m {}", target_name);
+    /// `libc@0.2.170` as cargo names packages in build script warnings
+    pub fn package_name(&self) -> String {
+        match &self.crate_version {
+            Some(version) => format!("{}@{}", self.crate_name, version),
+            None => self.crate_name.clone(),
+        }
+    }
+}
 
-    logone.print_message(0, msg.as_str(), None);
+/// A finished crate target in the shape of cargo's `compiler-artifact` JSON message.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompilerArtifact {
+    pub reason: &'static str,
+    pub package_id: String,
+    pub target: ArtifactTarget,
+    pub filenames: Vec<String>,
+}
 
-    Ok(())
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactTarget {
+    pub name: String,
+    /// "lib", "bin", "custom-build", ... derived from the `crate_type` of the session
+    pub kind: Vec<String>,
 }
 
-// @cargo {type: 2, id: $fullname, crate_name: $crate_name, crate_type: $crate_type, rustc_exit_code: ($exit_code|tonumber), rustc_messages: [ { rendered: "..." }, { rendered: "..." }, ... ]}
-pub fn handle_cargo_log_rustc_exit(
-    obj: &Map<String, Value>,
-    logone: &mut logone::LogOne,
-) -> Result<()> {
-    //println!("{:#?}", obj);
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log phase"))?;
+impl CompilerArtifact {
+    /// `None` for sessions which did not produce files, e.g. `(build.rs run)`.
+    pub fn from_session(session: &CargoSession) -> Option<CompilerArtifact> {
+        let filenames: Vec<String> = session
+            .artifacts
+            .iter()
+            .filter(|artifact| artifact.emit != "dep-info")
+            .map(|artifact| artifact.artifact.clone())
+            .collect();
+        if filenames.is_empty() {
+            return None;
+        }
+        let kind = match session.crate_type.trim_matches(|c| c == '(' || c == ')') {
+            "build.rs build" => "custom-build".to_string(),
+            kind => kind.to_string(),
+        };
+        Some(CompilerArtifact {
+            reason: "compiler-artifact",
+            package_id: session.package_name(),
+            target: ArtifactTarget {
+                name: session.crate_name.clone(),
+                kind: vec![kind],
+            },
+            filenames,
+        })
+    }
+}
 
-    let target_name = get_target_name(&obj);
+/// The files produced by every crate target which finished so far, in the order they finished.
+pub fn compiler_artifacts(logone: &logone::LogOne) -> Vec<CompilerArtifact> {
+    logone
+        .finished_sessions
+        .iter()
+        .filter_map(|(_, session)| CompilerArtifact::from_session(session))
+        .collect()
+}
 
-    logone.target_remove(target_name.clone())?;
+// the derivation of a crate is named <crate_name>-<version with _ for .>-[<target>-]<hash>
+fn version_from_derivation(crate_name: &str, text: &str) -> Option<String> {
+    let name = derivation_name(text);
+    let rest = name.strip_prefix(crate_name)?.strip_prefix('-')?;
+    let version = rest.split('-').next()?;
+    if version.starts_with(|c: char| c.is_ascii_digit()) {
+        Some(version.replace('_', "."))
+    } else {
+        None
+    }
+}
 
-    let rustc_exit_code: u64 = obj.get("rustc_exit_code").and_then(|v| v.as_u64()).unwrap();
+fn get_string(obj: &Map<String, Value>, field: &str) -> String {
+    obj.get(field)
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string()
+}
 
-    let rendered_messages: Vec<String> = obj
-        .get("rustc_messages")
+// type 2 sends rustc's JSON diagnostics, type 3 and type 1 send plain strings
+fn get_messages(obj: &Map<String, Value>) -> Vec<String> {
+    obj.get("rustc_messages")
+        .or_else(|| obj.get("messages"))
         .and_then(|msgs| msgs.as_array())
         .map(|msgs| {
             msgs.iter()
-                .filter_map(|msg| {
-                    msg.as_object()
-                        .and_then(|obj| obj.get("rendered"))
+                .filter_map(|msg| match msg {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Object(obj) => obj
+                        .get("rendered")
                         .and_then(|value| value.as_str())
-                        .map(|s| s.to_string())
+                        .map(|s| s.to_string()),
+                    _ => None,
                 })
                 .collect()
         })
-        .unwrap_or_else(Vec::new);
+        .unwrap_or_default()
+}
+
+// the rendered messages to print in cargo mode, with the rustc diagnostics which
+// LogOne::show_diagnostic hides left out, and the diagnostics behind them
+fn visible_messages(
+    obj: &Map<String, Value>,
+    crate_name: &str,
+    logone: &mut logone::LogOne,
+) -> Vec<(String, Option<RustcDiagnostic>)> {
+    let msgs = match obj
+        .get("rustc_messages")
+        .or_else(|| obj.get("messages"))
+        .and_then(|msgs| msgs.as_array())
+    {
+        Some(msgs) => msgs,
+        None => return Vec::new(),
+    };
+    let mut visible = Vec::new();
+    let mut hidden = 0;
+    for msg in msgs {
+        let rendered = match msg {
+            Value::String(s) => s.clone(),
+            Value::Object(obj) => match obj.get("rendered").and_then(|value| value.as_str()) {
+                Some(rendered) => rendered.to_string(),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let diagnostic = RustcDiagnostic::from_value(msg);
+        if let Some(diagnostic) = &diagnostic {
+            // "N warnings emitted" would count the hidden ones too
+            let shown = if diagnostic.is_summary() && hidden > 0 {
+                false
+            } else {
+                logone.show_diagnostic(crate_name, diagnostic)
+            };
+            if !shown {
+                hidden += 1;
+                continue;
+            }
+        }
+        visible.push((rendered, diagnostic));
+    }
+    visible
+}
+
+// /nix/store/<hash>-<name> of the first artifact
+fn get_out_path(artifacts: &[RustcArtifact]) -> Option<String> {
+    let artifact = artifacts.first()?;
+    let rest = artifact.artifact.strip_prefix("/nix/store/")?;
+    let name = rest.split('/').next()?;
+    Some(format!("/nix/store/{}", name))
+}
+
+fn get_artifacts(obj: &Map<String, Value>) -> Vec<RustcArtifact> {
+    obj.get("rustc_messages")
+        .and_then(|msgs| msgs.as_array())
+        .map(|msgs| msgs.iter().filter_map(RustcArtifact::from_value).collect())
+        .unwrap_or_default()
+}
+
+fn get_diagnostics(obj: &Map<String, Value>) -> Vec<RustcDiagnostic> {
+    obj.get("rustc_messages")
+        .and_then(|msgs| msgs.as_array())
+        .map(|msgs| {
+            msgs.iter()
+                .filter_map(RustcDiagnostic::from_value)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_active(id: u64, logone: &logone::LogOne) -> bool {
+    logone.cargo_log_buffers_state.get(&id) == Some(&LogStatus::Started)
+}
+
+fn protocol_violation(id: u64, violation: &'static str, logone: &mut logone::LogOne) {
+    logone.warn(LogoneError::CargoProtocol { id, violation });
+}
+
+// @cargo {type: 0, crate_name: $crate_name, crate_type: $crate_type}
+pub fn handle_session_start(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
+    let id = get_id(obj, "cargo start")?;
 
-    //println!("{:#?}", rendered_messages);
+    if is_active(id, logone) {
+        // the previous session never ended, replace it
+        protocol_violation(
+            id,
+            "start of a new session before the end of the previous one",
+            logone,
+        );
+        if let Some(session) = logone.cargo_sessions.remove(&id) {
+            logone.target_remove(session.target_name())?;
+        }
+    }
+
+    let crate_name = get_string(obj, "crate_name");
+    let crate_version = obj
+        .get("crate_version")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
+        .or_else(|| {
+            let activity = logone.activities.get(&id)?;
+            version_from_derivation(&crate_name, &activity.text)
+        });
+    let session = CargoSession {
+        crate_name,
+        crate_type: get_string(obj, "crate_type"),
+        crate_version,
+        exit_status: None,
+        derivation: logone.id_to_drv.get(&id).cloned(),
+        out_path: None,
+        started: Utc::now(),
+        finished: None,
+        ended_by_stop: false,
+        build_script: None,
+        diagnostics: Vec::new(),
+        artifacts: Vec::new(),
+        messages: Vec::new(),
+    };
+    let target_name = session.target_name();
 
+    // Create new log buffer for this id
+    logone.cargo_log_buffers.insert(id, Vec::new());
     logone
         .cargo_log_buffers_state
         .insert(id, LogStatus::Started);
-    match rustc_exit_code {
-        0 => {
-            logone
-                .cargo_log_buffers_state
-                .insert(id, LogStatus::FinishedWithSuccess);
-        }
-        _ => {
-            logone
-                .cargo_log_buffers_state
-                .insert(id, LogStatus::FinishedWithError);
+    if logone.has_event_handlers() {
+        logone.emit(Event::CrateCompiling {
+            id,
+            session: session.clone(),
+        });
+    }
+    logone.cargo_sessions.insert(id, session);
+
+    logone.target_add(target_name.clone())?;
+
+    let msg: String = format!("   \x1b[32mCompiling\x1b[0m {}", target_name);
+
+    logone.print_message(0, msg.as_str(), None);
+
+    Ok(())
+}
+
+// @cargo {type: 1, crate_name: $crate_name, crate_type: $crate_type, messages: [ "a", "b", "c" ]}
+pub fn handle_session_message(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
+    let id = get_id(obj, "cargo message")?;
+
+    if !is_active(id, logone) {
+        protocol_violation(id, "message without an active session", logone);
+        return Ok(());
+    }
+
+    let messages = get_messages(obj);
+
+    if let Some(buffer) = logone.cargo_log_buffers.get_mut(&id) {
+        buffer.extend(messages.iter().cloned());
+    }
+
+    if logone.level().handles_cargo() {
+        for msg in messages.iter() {
+            logone.print_message(4, msg.as_str(), None);
         }
+    }
+
+    for message in messages {
+        logone.emit(Event::CrateMessage { id, message });
+    }
+
+    Ok(())
+}
+
+// type 2, sent by rustc builds (lib), (bin), (build.rs build):
+// @cargo {type: 2, crate_name: $crate_name, crate_type: $crate_type, rustc_exit_code: ($exit_code|tonumber), rustc_messages: [ { rendered: "..." }, ... ]}
+// type 3, sent by (build.rs run):
+// @cargo {type: 3, crate_name: $crate_name, crate_type: $crate_type, exit_code: ($exit_code|tonumber), messages: [ "a", "b", "c" ]}
+// both shapes are accepted for either type
+pub fn handle_session_end(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
+    let id = get_id(obj, "cargo end")?;
+
+    if !is_active(id, logone) {
+        protocol_violation(id, "end without start", logone);
+        return Ok(());
+    }
+
+    let exit_status = match obj.get("rustc_exit_code").or_else(|| obj.get("exit_code")) {
+        Some(value) => ExitStatus::from_value(value),
+        None => ExitStatus::Unknown,
+    };
+    if exit_status == ExitStatus::Unknown {
+        // still end the session, as failed, so it leaves the status line
+        protocol_violation(id, "end without a valid exit code", logone);
+    }
+
+    let messages = get_messages(obj);
+
+    // type 3 is sent by (build.rs run)
+    let build_script = match obj.get("type").and_then(|v| v.as_u64()) {
+        Some(3) => Some(BuildScriptOutput::parse(
+            messages.iter().flat_map(|msg| msg.lines()),
+        )),
+        _ => None,
+    };
+
+    if let Some(session) = logone.cargo_sessions.get_mut(&id) {
+        session.exit_status = Some(exit_status);
+        session.build_script = build_script.clone();
+        session.diagnostics = get_diagnostics(obj);
+        session.artifacts = get_artifacts(obj);
+        session.out_path = get_out_path(&session.artifacts);
+        session.finished = Some(Utc::now());
+        let target_name = session.target_name();
+        logone.target_remove(target_name)?;
+    }
+
+    let state = if exit_status.success() {
+        LogStatus::FinishedWithSuccess
+    } else {
+        LogStatus::FinishedWithError
+    };
+    logone.cargo_log_buffers_state.insert(id, state);
+
+    // print_message colours by level, signals and unknown codes are shown like errors
+    let level: u64 = match exit_status {
+        ExitStatus::Code(code) if code >= 0 => code as u64,
+        _ => 0,
     };
 
     if let Some(buffer) = logone.cargo_log_buffers.get_mut(&id) {
-        for msg in rendered_messages.clone() {
-            buffer.push(msg);
+        buffer.extend(messages.iter().cloned());
+        if let Some(session) = logone.cargo_sessions.get_mut(&id) {
+            session.messages = buffer.clone();
+        }
+    }
+
+    if logone.level().handles_cargo() {
+        match &build_script {
+            // like cargo: only the warnings of successful build scripts are shown
+            Some(build_script) if exit_status.success() => {
+                print_build_script_output(id, build_script, logone);
+            }
+            _ => {
+                let crate_name = logone
+                    .cargo_sessions
+                    .get(&id)
+                    .map(|session| session.crate_name.clone())
+                    .unwrap_or_default();
+                for (msg, diagnostic) in visible_messages(obj, &crate_name, logone) {
+                    let file: Option<&str> = None;
+                    logone.print_message(level, msg.as_str(), file);
+                    let annotation = match (logone.ci(), &diagnostic) {
+                        (Some(CiFormat::Github), Some(diagnostic)) if !diagnostic.is_summary() => {
+                            ci::github_annotation(diagnostic)
+                        }
+                        _ => None,
+                    };
+                    if let Some(annotation) = annotation {
+                        logone.print_line(&annotation);
+                    }
+                }
+            }
+        }
+        if !exit_status.success() && !matches!(exit_status, ExitStatus::Code(_)) {
+            let target_name = logone
+                .cargo_sessions
+                .get(&id)
+                .map(|session| session.target_name())
+                .unwrap_or_default();
+            let msg = format!("error: {} failed: {}", target_name, exit_status);
+            logone.print_message(0, msg.as_str(), None);
+        }
+        if !exit_status.success() {
+            if let Some(drv) = logone.id_to_drv.get(&id) {
+                let msg = format!("note: for the full build log, run 'nix log {}'", drv);
+                logone.print_message(2, msg.as_str(), None);
+            }
         }
     }
 
-    if logone.level() == logone::LogLevel::Cargo {
-        for msg in rendered_messages {
-            let file: Option<&str> = None;
-            logone.print_message(rustc_exit_code, msg.as_str(), file);
+    if let Some(session) = logone.cargo_sessions.get(&id) {
+        let session = session.clone();
+        logone.finished_sessions.push((id, session.clone()));
+        let artifact = CompilerArtifact::from_session(&session);
+        if logone.has_event_handlers() {
+            for diagnostic in session.diagnostics.iter().filter(|d| !d.is_summary()) {
+                logone.emit(Event::Diagnostic {
+                    id,
+                    crate_name: session.crate_name.clone(),
+                    crate_type: session.crate_type.clone(),
+                    drv: session.derivation.clone(),
+                    diagnostic: diagnostic.clone(),
+                });
+            }
+        }
+        logone.emit(Event::CrateFinished { id, session });
+        if let Some(artifact) = artifact {
+            logone.emit(Event::CrateArtifact { id, artifact });
         }
     }
 
     Ok(())
 }
 
-// @cargo {type: 3, crate_name: $crate_name, crate_type: $crate_type, exit_code: ($exit_code|tonumber), messages: [ "a", "b", "c" ]}
-pub fn handle_cargo_log_build_exit(
-    obj: &Map<String, Value>,
+// the `-script_build_run-` derivations of libnix run a build script, their type 0
+// often has no crate_type
+fn is_build_script_run(session: &CargoSession) -> bool {
+    session.crate_type == "(build.rs run)"
+        || session
+            .derivation
+            .as_deref()
+            .is_some_and(|drv| drv.contains("-script_build_run-"))
+}
+
+// build script runs send no type 3 when there was nothing to report, the stop of
+// their activity ends the session. A failed builder is only reported by nix after
+// the stop, until then the run counts as successful, see handle_builder_failure.
+fn end_build_script_run(id: u64, logone: &mut logone::LogOne) -> Result<()> {
+    let messages = logone
+        .cargo_log_buffers
+        .get(&id)
+        .cloned()
+        .unwrap_or_default();
+    let session = match logone.cargo_sessions.get_mut(&id) {
+        Some(session) => session,
+        None => return Ok(()),
+    };
+    session.exit_status = Some(ExitStatus::Code(0));
+    session.finished = Some(Utc::now());
+    session.ended_by_stop = true;
+    session.messages = messages;
+    let session = session.clone();
+    logone.target_remove(session.target_name())?;
+    logone
+        .cargo_log_buffers_state
+        .insert(id, LogStatus::FinishedWithSuccess);
+    logone.finished_sessions.push((id, session.clone()));
+    logone.emit(Event::CrateFinished { id, session });
+    Ok(())
+}
+
+fn print_build_script_output(
+    id: u64,
+    build_script: &BuildScriptOutput,
     logone: &mut logone::LogOne,
-) -> Result<()> {
-    //println!("{:#?}", obj);
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log phase"))?;
+) {
+    let (package_name, local) = logone
+        .cargo_sessions
+        .get(&id)
+        .map(|session| {
+            (
+                session.package_name(),
+                logone.is_local_crate(&session.crate_name),
+            )
+        })
+        .unwrap_or_default();
+    let github = logone.ci() == Some(CiFormat::Github);
+    for (severity, text) in build_script_messages(local, build_script) {
+        let msg = format!("{}: {}: {}", severity, package_name, text);
+        logone.print_message(if severity == "error" { 0 } else { 1 }, msg.as_str(), None);
+        if github {
+            logone.print_line(&ci::github_message(severity, &package_name, text));
+        }
+    }
+}
 
-    let target_name = get_target_name(&obj);
+// the warnings and errors cargo prints for a build script, by severity
+fn build_script_messages(
+    local: bool,
+    build_script: &BuildScriptOutput,
+) -> Vec<(&'static str, &str)> {
+    // cargo only shows the build script warnings of workspace members
+    let warnings = if local {
+        &build_script.warnings[..]
+    } else {
+        &[]
+    };
+    let warnings = warnings.iter().map(|warning| ("warning", warning.as_str()));
+    let errors = build_script
+        .errors
+        .iter()
+        .map(|error| ("error", error.as_str()));
+    warnings.chain(errors).collect()
+}
 
-    logone.target_remove(target_name.clone())?;
+/// Records the exit status nix reported for the failed builder of activity `id` on a
+/// build script run which ended with the stop of the activity.
+pub fn handle_builder_failure(id: u64, exit_status: ExitStatus, logone: &mut logone::LogOne) {
+    let session = match logone.cargo_sessions.get_mut(&id) {
+        Some(session) if session.ended_by_stop => session,
+        _ => return,
+    };
+    session.exit_status = Some(exit_status);
+    let session = session.clone();
+    logone
+        .cargo_log_buffers_state
+        .insert(id, LogStatus::FinishedWithError);
+    if let Some((_, finished)) = logone
+        .finished_sessions
+        .iter_mut()
+        .rev()
+        .find(|(finished_id, _)| *finished_id == id)
+    {
+        *finished = session;
+    }
+}
 
-    let exit_code: u64 = obj.get("exit_code").and_then(|v| v.as_u64()).unwrap();
+// the nix activity carrying the session stopped, without the session having ended
+pub fn handle_activity_stop(id: u64, logone: &mut logone::LogOne) -> Result<()> {
+    if !is_active(id, logone) {
+        return Ok(());
+    }
 
-    let messages: Vec<String> = obj
-        .get("messages")
-        .and_then(|msgs| msgs.as_array())
-        .map(|msgs| {
-            msgs.iter()
-                .filter_map(|msg| msg.as_str().map(|s| s.to_string()))
-                .collect()
-        })
-        .unwrap_or_else(Vec::new);
+    if logone
+        .cargo_sessions
+        .get(&id)
+        .is_some_and(is_build_script_run)
+    {
+        return end_build_script_run(id, logone);
+    }
 
-    // println!("{:#?}", messages);
+    protocol_violation(id, "activity stopped before the end of its session", logone);
 
     logone
         .cargo_log_buffers_state
-        .insert(id, LogStatus::Started);
-    match exit_code {
-        0 => {
-            logone
-                .cargo_log_buffers_state
-                .insert(id, LogStatus::FinishedWithSuccess);
-        }
-        _ => {
-            logone
-                .cargo_log_buffers_state
-                .insert(id, LogStatus::FinishedWithError);
-        }
-    };
+        .insert(id, LogStatus::Stopped);
+    if let Some(session) = logone.cargo_sessions.get(&id) {
+        let target_name = session.target_name();
+        logone.target_remove(target_name)?;
+    }
 
-    if let Some(buffer) = logone.cargo_log_buffers.get_mut(&id) {
-        for msg in messages.clone() {
-            buffer.push(msg);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{rustc_artifact, Generator};
+    use crate::logone::{LogLevel, LogOne};
+    use crate::parser::parse_nix_line;
+
+    fn replay(gen: &Generator) -> LogOne {
+        let mut logone = LogOne::new(false, LogLevel::Cargo);
+        for line in gen.lines() {
+            parse_nix_line(line, &mut logone).unwrap();
         }
+        logone
+    }
+
+    fn json_rendered(rendered: &str) -> Value {
+        serde_json::json!({ "rendered": rendered })
+    }
+
+    #[test]
+    fn exit_status_from_value() {
+        use serde_json::json;
+        assert_eq!(ExitStatus::from_value(&json!(0)), ExitStatus::Code(0));
+        assert_eq!(ExitStatus::from_value(&json!(1)), ExitStatus::Code(1));
+        assert_eq!(ExitStatus::from_value(&json!(101)), ExitStatus::Code(101));
+        assert_eq!(ExitStatus::from_value(&json!(130)), ExitStatus::Code(130));
+        assert_eq!(ExitStatus::from_value(&json!("137")), ExitStatus::Code(137));
+        assert_eq!(ExitStatus::from_value(&json!(-9)), ExitStatus::Signal(9));
+        assert_eq!(ExitStatus::from_value(&json!(null)), ExitStatus::Unknown);
+        assert_eq!(ExitStatus::from_value(&json!("")), ExitStatus::Unknown);
+        assert!(ExitStatus::Code(0).success());
+        assert!(!ExitStatus::Code(130).success());
+    }
+
+    #[test]
+    fn session_lifecycle() {
+        let mut gen = Generator::new(31);
+        let build = gen.build_start("libc-0_2_170-script_build_run-00cb1c0a8601ad49");
+        gen.cargo_start(build.id, "libc", "(build.rs run)");
+        gen.cargo_message(
+            build.id,
+            "libc",
+            "(build.rs run)",
+            &["running build script"],
+        );
+        gen.cargo_build_exit(
+            build.id,
+            "libc",
+            "(build.rs run)",
+            0,
+            &["cargo:rustc-cfg=freebsd11"],
+        );
+        gen.cargo_start(build.id, "libc", "(lib)");
+        gen.cargo_rustc_exit(
+            build.id,
+            "libc",
+            "(lib)",
+            1,
+            vec![json_rendered("error: oops")],
+        );
+        gen.stop(build.id);
+
+        let logone = replay(&gen);
+        let session = &logone.cargo_sessions[&build.id];
+        assert_eq!(session.target_name(), "libc (lib)");
+        assert_eq!(session.exit_status, Some(ExitStatus::Code(1)));
+        assert_eq!(
+            logone.cargo_log_buffers_state[&build.id],
+            LogStatus::FinishedWithError
+        );
+        assert_eq!(logone.cargo_log_buffers[&build.id], vec!["error: oops"]);
+        assert_eq!(logone.diagnostics.count(), 0);
+    }
+
+    #[test]
+    fn protocol_violations() {
+        let mut gen = Generator::new(32);
+        let build = gen.build_start("serde-1_0_218-472e28b9f131b02c");
+        gen.cargo_message(build.id, "serde", "(lib)", &["too early"]);
+        gen.cargo_rustc_exit(build.id, "serde", "(lib)", 0, vec![]);
+        gen.cargo_start(build.id, "serde", "(lib)");
+        gen.cargo_start(build.id, "serde", "(test)");
+        gen.cargo_rustc_exit(build.id, "serde", "(test)", 0, vec![]);
+        gen.stop(build.id);
+
+        let logone = replay(&gen);
+        assert_eq!(logone.diagnostics.counts().get("cargo-protocol"), Some(&3));
+        // the second start replaced the session which never ended
+        assert_eq!(
+            logone.cargo_sessions[&build.id].target_name(),
+            "serde (test)"
+        );
+        assert!(logone.cargo_log_buffers[&build.id].is_empty());
     }
 
-    if logone.level() == logone::LogLevel::Cargo {
-        for msg in messages {
-            let file: Option<&str> = None;
-            logone.print_message(exit_code, msg.as_str(), file);
+    #[test]
+    fn build_script_runs_end_with_their_activity() {
+        let mut logone = LogOne::new(false, LogLevel::Cargo);
+        for line in include_str!("../../examples/example.stdin11").lines() {
+            let _ = parse_nix_line(line, &mut logone);
         }
+        let runs: Vec<&CargoSession> = logone
+            .cargo_sessions
+            .values()
+            .filter(|session| is_build_script_run(session))
+            .collect();
+        assert_eq!(runs.len(), 8);
+        assert!(runs
+            .iter()
+            .all(|session| session.exit_status == Some(ExitStatus::Code(0))));
+        // only the flate2 build, which the recording contains twice, is out of protocol
+        let counts = logone.diagnostics.counts();
+        assert_eq!(counts.get("cargo-protocol"), Some(&1));
+        assert_eq!(counts.get("duplicate-start"), Some(&1));
+        assert_eq!(logone.diagnostics.count(), 2);
     }
 
-    Ok(())
+    #[test]
+    fn build_script_instructions() {
+        let mut gen = Generator::new(35);
+        let build = gen.build_start("libc-0_2_170-script_build_run-00cb1c0a8601ad49");
+        gen.cargo_start(build.id, "libc", "(build.rs run)");
+        gen.cargo_build_exit(
+            build.id,
+            "libc",
+            "(build.rs run)",
+            0,
+            &["cargo:rustc-cfg=freebsd11\ncargo:rerun-if-changed=build.rs"],
+        );
+        gen.stop(build.id);
+
+        let logone = replay(&gen);
+        let session = &logone.cargo_sessions[&build.id];
+        assert_eq!(session.package_name(), "libc@0.2.170");
+        let build_script = session.build_script.as_ref().unwrap();
+        assert_eq!(build_script.rustc_cfg, ["freebsd11"]);
+        assert_eq!(build_script.rerun_if_changed, ["build.rs"]);
+    }
+
+    #[test]
+    fn build_script_warnings_of_local_crates() {
+        let build_script = BuildScriptOutput::parse([
+            "cargo:warning=zlib not found, building it",
+            "cargo:error=no C compiler",
+        ]);
+        assert_eq!(
+            build_script_messages(true, &build_script),
+            vec![
+                ("warning", "zlib not found, building it"),
+                ("error", "no C compiler"),
+            ]
+        );
+        assert_eq!(
+            build_script_messages(false, &build_script),
+            vec![("error", "no C compiler")]
+        );
+    }
+
+    #[test]
+    fn compiler_artifacts_of_finished_targets() {
+        let mut gen = Generator::new(37);
+        let build = gen.build_start("memchr-2_7_4-3cee6db17bbe0dde");
+        let out = "/nix/store/0aa-memchr-2_7_4-3cee6db17bbe0dde-lib/lib";
+        gen.cargo_start(build.id, "memchr", "(lib)");
+        gen.cargo_rustc_exit(
+            build.id,
+            "memchr",
+            "(lib)",
+            0,
+            vec![
+                rustc_artifact(&format!("{}/memchr-3cee6db17bbe0dde.d", out), "dep-info"),
+                rustc_artifact(
+                    &format!("{}/libmemchr-3cee6db17bbe0dde.rmeta", out),
+                    "metadata",
+                ),
+                rustc_artifact(&format!("{}/libmemchr-3cee6db17bbe0dde.rlib", out), "link"),
+            ],
+        );
+        // only writes a dep-info file
+        gen.cargo_start(build.id, "memchr", "(test)");
+        gen.cargo_rustc_exit(
+            build.id,
+            "memchr",
+            "(test)",
+            0,
+            vec![rustc_artifact(&format!("{}/memchr.d", out), "dep-info")],
+        );
+        gen.stop(build.id);
+
+        let logone = replay(&gen);
+        let artifacts = compiler_artifacts(&logone);
+        assert_eq!(artifacts.len(), 1);
+        let artifact = &artifacts[0];
+        assert_eq!(artifact.reason, "compiler-artifact");
+        assert_eq!(artifact.package_id, "memchr@2.7.4");
+        assert_eq!(artifact.target.name, "memchr");
+        assert_eq!(artifact.target.kind, ["lib"]);
+        assert_eq!(
+            artifact.filenames,
+            [
+                format!("{}/libmemchr-3cee6db17bbe0dde.rmeta", out),
+                format!("{}/libmemchr-3cee6db17bbe0dde.rlib", out),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_builder_of_build_script_run() {
+        let mut gen = Generator::new(41);
+        let run = gen.build_start("ring-0_17_8-script_build_run-1a2b3c4d5e6f7a8b");
+        gen.cargo_start(run.id, "ring", "");
+        gen.stop(run.id);
+        gen.build_failed(&run, 101, &["thread 'main' panicked at build.rs:12:5"]);
+
+        let logone = replay(&gen);
+        let session = &logone.cargo_sessions[&run.id];
+        assert!(session.ended_by_stop);
+        assert_eq!(session.exit_status, Some(ExitStatus::Code(101)));
+        assert_eq!(
+            logone.cargo_log_buffers_state[&run.id],
+            LogStatus::FinishedWithError
+        );
+        assert_eq!(
+            logone.finished_sessions[0].1.exit_status,
+            Some(ExitStatus::Code(101))
+        );
+    }
 }
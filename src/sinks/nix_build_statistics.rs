@@ -1,5 +1,9 @@
-use crate::logone;
-use anyhow::{anyhow, Result};
+use crate::{
+    error::{LogoneError, Result},
+    events::Event,
+    logone,
+    parser::get_id,
+};
 use serde_json::{Map, Value};
 use std::collections::HashSet;
 use std::sync::{LazyLock, Mutex};
@@ -12,10 +16,7 @@ static mut FAILED: u64 = 0;
 static STATUS_IDS: LazyLock<Mutex<HashSet<u64>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
 
 pub fn handle_status_start(obj: &Map<String, Value>, _: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in stats start"))?;
+    let id = get_id(obj, "stats start")?;
 
     // Track this as a status ID
     if let Ok(mut status_ids) = STATUS_IDS.lock() {
@@ -25,30 +26,31 @@ pub fn handle_status_start(obj: &Map<String, Value>, _: &mut logone::LogOne) ->
 }
 
 pub fn handle_status_update(obj: &Map<String, Value>, display: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in stats update"))?;
+    let id = get_id(obj, "stats update")?;
 
     // Check if the id is in STATUS_IDS
     if let Ok(status_ids) = STATUS_IDS.lock() {
         if !status_ids.contains(&id) {
-            return Err(anyhow!("Unknown id in stats update: {}", id));
+            return Err(LogoneError::UnknownStatusId(id));
         }
     } else {
-        return Err(anyhow!("Failed to lock STATS_IDS"));
+        return Err(LogoneError::LockPoisoned);
     }
 
     let fields = obj
         .get("fields")
         .and_then(|v| v.as_array())
-        .ok_or_else(|| anyhow!("Missing fields array in stats update"))?;
+        .ok_or(LogoneError::MissingField {
+            field: "fields",
+            action: "stats update",
+        })?;
 
     if fields.len() != 4 {
-        return Err(anyhow!(
-            "Expected 4 fields in stats update, got {}",
-            fields.len()
-        ));
+        return Err(LogoneError::FieldCount {
+            action: "stats update",
+            expected: 4,
+            found: fields.len(),
+        });
     }
 
     let done = fields[0].as_u64().unwrap_or(0);
@@ -65,15 +67,18 @@ pub fn handle_status_update(obj: &Map<String, Value>, display: &mut logone::LogO
     }
 
     update_stats_display(display)?;
+    display.emit(Event::Stats {
+        done,
+        expected,
+        running,
+        failed,
+    });
 
     Ok(())
 }
 
 pub fn handle_status_stop(obj: &Map<String, Value>, display: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in stats stop"))?;
+    let id = get_id(obj, "stats stop")?;
 
     // Remove from stats IDs
     if let Ok(mut status_ids) = STATUS_IDS.lock() {
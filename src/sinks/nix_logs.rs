@@ -1,14 +1,13 @@
-use crate::logone;
-use anyhow::{anyhow, Result};
-use logone::{LogStatus, NixMessage};
-use regex::Regex;
+use crate::{
+    error::Result,
+    logone,
+    parser::{derivation_re, get_id},
+};
+use logone::{derivation_name, LogStatus, NixMessage};
 use serde_json::{Map, Value};
 
 pub fn handle_log_start(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log start"))?;
+    let id = get_id(obj, "log start")?;
 
     let text = obj
         .get("text")
@@ -16,22 +15,24 @@ pub fn handle_log_start(obj: &Map<String, Value>, logone: &mut logone::LogOne) -
         .unwrap_or("")
         .to_string();
 
-    // Create new log buffer for this id
-    logone.nix_log_buffers.insert(id, Vec::new());
-    logone.nix_log_buffers_state.insert(id, LogStatus::Started);
-
     // Map id to derivation name
     //println!("{}", text.clone());
     logone.drv_to_id.insert(text.clone(), id);
 
+    // Without a buffer the lines of excluded derivations are dropped
+    if !logone.filter().shows_derivation(&derivation_name(&text)) {
+        return Ok(());
+    }
+
+    // Create new log buffer for this id
+    logone.nix_log_buffers.insert(id, Vec::new());
+    logone.nix_log_buffers_state.insert(id, LogStatus::Started);
+
     Ok(())
 }
 
 pub fn handle_log_line(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log line"))?;
+    let id = get_id(obj, "log line")?;
 
     let fields = obj.get("fields").and_then(|v| v.as_array());
 
@@ -45,6 +46,10 @@ pub fn handle_log_line(obj: &Map<String, Value>, logone: &mut logone::LogOne) ->
         String::new()
     };
 
+    if logone.filter().hides_message(&content) {
+        return Ok(());
+    }
+
     let message = NixMessage {
         action: "result".to_string(),
         message_type: Some(101), // resBuildLogLine
@@ -62,10 +67,7 @@ pub fn handle_log_line(obj: &Map<String, Value>, logone: &mut logone::LogOne) ->
 }
 
 pub fn handle_log_phase(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log phase"))?;
+    let id = get_id(obj, "log phase")?;
 
     let fields = obj.get("fields").and_then(|v| v.as_array());
 
@@ -96,10 +98,7 @@ pub fn handle_log_phase(obj: &Map<String, Value>, logone: &mut logone::LogOne) -
 }
 
 pub fn handle_log_stop(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Result<()> {
-    let id = obj
-        .get("id")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow!("Missing id in log phase"))?;
+    let id = get_id(obj, "log stop")?;
     logone.nix_log_buffers_state.insert(id, LogStatus::Stopped);
     Ok(())
 }
@@ -109,22 +108,24 @@ pub fn handle_msg(obj: &Map<String, Value>, logone: &mut logone::LogOne) -> Resu
     let msg = obj.get("msg").and_then(|v| v.as_str()).unwrap_or("");
     let file = obj.get("file").and_then(|v| v.as_str());
 
-    let re = Regex::new(r#"/nix/store/([a-zA-Z0-9_.+-]+).drv"#).unwrap();
-    let captures = re.captures(msg);
-
-    match captures {
-        Some(c) => {
-            if let Some(c) = c.get(1) {
-                // lv24iib6cgsr1ipkz4gpf2agf08bxj6n-cargo-0_88_0-d76731b471aa2da9
-                let drv: String = format!("building '/nix/store/{}.drv'", c.as_str());
-                logone.print_log_buffer_by_drv(drv);
+    let captures = derivation_re().captures(msg);
+
+    if let Some(c) = captures.and_then(|c| c.get(1)) {
+        // lv24iib6cgsr1ipkz4gpf2agf08bxj6n-cargo-0_88_0-d76731b471aa2da9
+        let drv: String = format!("building '/nix/store/{}.drv'", c.as_str());
+        if level == 0 {
+            // "builder for '...drv' failed with exit code 1"
+            if let Some(&id) = logone.drv_to_id.get(&drv) {
+                logone
+                    .nix_log_buffers_state
+                    .insert(id, LogStatus::FinishedWithError);
             }
         }
-        None => {}
+        logone.print_log_buffer_by_drv(drv);
     }
 
-    // Show messages with level 1-3 (WARN, NOTICE, INFO)
-    if level >= 1 && level <= 3 {
+    // Show messages with level 1-3 (WARN, NOTICE, INFO), unless the filter says otherwise
+    if logone.filter().shows_level(level, (1..=3).contains(&level)) {
         logone.print_message(level, msg, file);
     }
 
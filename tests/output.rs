@@ -0,0 +1,144 @@
+//! Runs the logone binary on generated `@nix` streams and checks what it prints.
+
+use logone::generator::Generator;
+use serde_json::json;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn logone(args: &[&str], gen: &Generator) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_logone"))
+        .args(["--json", "--no-color"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for line in gen.lines() {
+        writeln!(stdin, "{}", line).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn hybrid() {
+    let mut gen = Generator::new(39);
+    let hello = gen.build_start("hello-2.12.1");
+    gen.log_line(hello.id, "checking for gcc... no");
+    gen.stop(hello.id);
+    gen.build_failed(&hello, 1, &["checking for gcc... no"]);
+
+    let quote = gen.build_start("quote-1_0_38-12b99e3192e30e82");
+    gen.log_line(quote.id, "quote: compiling object 1 of 4");
+    gen.cargo_start(quote.id, "quote", "(lib)");
+    gen.cargo_rustc_exit(
+        quote.id,
+        "quote",
+        "(lib)",
+        1,
+        vec![json!({ "rendered": "error[E0425]: cannot find value `tokens` in this scope" })],
+    );
+    gen.stop(quote.id);
+    gen.build_failed(&quote, 1, &["error: could not compile `quote`"]);
+
+    gen.eval_error("/home/user/flake.nix", 12, 5, "undefined variable 'pkgs'");
+
+    let out = logone(&["--level", "hybrid"], &gen);
+    // the failed derivation without @cargo sessions gets its nix log
+    assert!(out.contains(&format!("Build log for 'building '{}''", hello.drv)));
+    assert!(out.contains("checking for gcc... no"));
+    // the failed crate only its cargo output
+    assert!(out.contains("error[E0425]: cannot find value `tokens` in this scope"));
+    assert!(!out.contains("quote: compiling object 1 of 4"));
+    assert!(!out.contains(&format!("builder for '{}'", quote.drv)));
+    assert!(out.contains("undefined variable 'pkgs'"));
+}
+
+#[test]
+fn filter() {
+    let mut gen = Generator::new(40);
+    let hello = gen.build_start("hello-2.12.1");
+    gen.log_line(hello.id, "checking for gcc... yes");
+    gen.log_line(hello.id, "trace: Using Cargo.dependencies.nix");
+    gen.stop(hello.id);
+    let bash = gen.build_start("bash-5.2p37");
+    gen.log_line(bash.id, "configure: creating Makefile");
+    gen.stop(bash.id);
+
+    let out = logone(
+        &[
+            "--level",
+            "errors",
+            "--always-show",
+            "^hello-",
+            "--hide-message",
+            "^trace: ",
+        ],
+        &gen,
+    );
+    // the successful hello is printed, bash is not
+    assert!(out.contains(&format!("Build log for 'building '{}''", hello.drv)));
+    assert!(out.contains("checking for gcc... yes"));
+    assert!(!out.contains("trace: Using Cargo.dependencies.nix"));
+    assert!(!out.contains("configure: creating Makefile"));
+}
+
+// runs logone on files instead of stdin, returns whether it succeeded and stderr
+fn logone_files(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_logone"))
+        .args(["--json", "--no-color"])
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn inputs() {
+    let mut gen = Generator::new(152);
+    let hello = gen.build_start("hello-2.12.1");
+    gen.stop(hello.id);
+    let dir = std::env::temp_dir().join(format!("logone-inputs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("build.log");
+    std::fs::write(&log, gen.lines().join("\n")).unwrap();
+    let junit = dir.join("report.xml");
+    let (log, junit, missing) = (
+        log.to_str().unwrap(),
+        junit.to_str().unwrap(),
+        dir.join("missing.log"),
+    );
+
+    // a missing input is reported before anything is read
+    let (success, stderr) = logone_files(&["--junit", junit, log, missing.to_str().unwrap()]);
+    assert!(!success);
+    assert!(stderr.contains("missing.log: No such file or directory"));
+    assert!(!std::path::Path::new(junit).exists());
+
+    // the reports cover what was read before a read error
+    let broken = dir.join("broken.log");
+    let mut content = gen.lines().join("\n").into_bytes();
+    content.extend(b"\n@nix {\"action\":\"msg\",\"msg\":\"\xff\"}\n");
+    std::fs::write(&broken, content).unwrap();
+    let (success, stderr) = logone_files(&["--junit", junit, broken.to_str().unwrap()]);
+    assert!(!success);
+    assert!(stderr.contains("reading the input failed"));
+    assert!(std::fs::read_to_string(junit)
+        .unwrap()
+        .contains(r#"<testcase name="hello-2.12.1""#));
+
+    let history = dir.join("history.jsonl");
+    let (success, stderr) = logone_files(&["--history", history.to_str().unwrap(), log]);
+    assert!(!success);
+    assert!(stderr.contains("--history can only record builds read from stdin"));
+    assert!(!history.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}